@@ -0,0 +1,313 @@
+use crate::device::Device;
+use crate::plan::{Planner, Transaction};
+use crate::psram::{
+    BurstLength, Freq, Header, Identification, Opcode, Payload, Phases, Settings, ID_LEN,
+    POWER_UP_US, RESET_US, WRAP_SIZE,
+};
+use crate::DeviceError;
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::SpiDevice;
+#[cfg(feature = "embedded-storage-async")]
+use embedded_storage_async::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Async driver for ESP SPI Psuedo SRAM chips on an `embedded-hal-async` `SpiDevice`.
+///
+/// Works like `spi_device::PsramDevice`, but every transaction is awaited, so
+/// other tasks can run while a long transfer is in progress.
+///
+/// # Type Parameters
+///
+/// * **`SPI`**: The SPI device the chip is attached to.
+#[derive(Debug)]
+pub struct PsramDevice<SPI: SpiDevice> {
+    spi: SPI,
+    settings: Settings,
+}
+
+impl<SPI: SpiDevice> PsramDevice<SPI> {
+    /// Creates a new PSRAM driver.
+    ///
+    /// Takes the same parameters as `PSRAM::init`, except that the chip select
+    /// line is driven by the `SpiDevice`.
+    pub async fn init(
+        spi: SPI,
+        freq: Freq,
+        burst_length: BurstLength,
+    ) -> Result<Self, DeviceError<SPI::Error>> {
+        Settings::check_init(freq, burst_length)?;
+        let mut this = Self {
+            spi,
+            settings: Settings::new(freq, burst_length),
+        };
+
+        //Set the burst_length now
+        if burst_length == BurstLength::ThirtyTwoByte {
+            this.command(Opcode::SetBurstLength).await?;
+        }
+
+        Ok(this)
+    }
+
+    /// Creates a new PSRAM driver, running the power up sequence from the datasheet.
+    ///
+    /// Waits 150µs for the device to power up, resets it, checks that the ID
+    /// belongs to a known good device the driver has a descriptor for and
+    /// then sets the burst length.
+    pub async fn init_with_delay<D: DelayNs>(
+        spi: SPI,
+        freq: Freq,
+        burst_length: BurstLength,
+        delay: &mut D,
+    ) -> Result<Self, DeviceError<SPI::Error>> {
+        Settings::check_init(freq, burst_length)?;
+        // The device starts without 32 byte wrapping, set_burst will change that if requested
+        let mut this = Self {
+            spi,
+            settings: Settings::new(freq, BurstLength::OneKByte),
+        };
+
+        delay.delay_us(POWER_UP_US).await;
+        this.reset(delay).await?;
+
+        let id = this.read_id().await?;
+        this.settings.check_id(&id)?;
+
+        this.set_burst(burst_length).await?;
+        Ok(this)
+    }
+
+    /// Releases the SPI device.
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+
+    /// The descriptor of the attached device.
+    pub fn device(&self) -> &Device {
+        &self.settings.device
+    }
+
+    /// Uses the given descriptor for the attached device. See `PSRAM::set_device`.
+    pub fn set_device(&mut self, device: Device) -> Result<(), DeviceError<SPI::Error>> {
+        Ok(self.settings.set_device(device)?)
+    }
+
+    /// Sets the maximum time CS may stay low, in nanoseconds. See `PSRAM::set_tcem`.
+    pub fn set_tcem(&mut self, nanoseconds: u32) {
+        self.settings.tcem = nanoseconds;
+    }
+
+    /// The maximum time CS may stay low, in nanoseconds.
+    pub fn tcem(&self) -> u32 {
+        self.settings.tcem
+    }
+
+    /// Sets the clock the SPI bus actually runs at, in MHz. See `PSRAM::set_clock_mhz`.
+    pub fn set_clock_mhz(&mut self, mhz: u32) {
+        self.settings.set_clock_mhz(mhz);
+    }
+
+    /// The clock the bus is assumed to actually run at, in MHz.
+    pub fn clock_mhz(&self) -> u32 {
+        self.settings.clock_mhz
+    }
+
+    /// The planner used for reads.
+    pub fn planner(&self) -> Planner {
+        self.settings.planner()
+    }
+
+    /// The current burst length.
+    pub fn burst_length(&self) -> BurstLength {
+        self.settings.burst_length
+    }
+
+    /// Reads the manufacturer/device identification and picks the device
+    /// descriptor. See `PSRAM::read_id`.
+    pub async fn read_id(&mut self) -> Result<Identification, DeviceError<SPI::Error>> {
+        let mut buf = [0; ID_LEN];
+        self.transaction(
+            Phases::single(Opcode::ReadID, 0),
+            0,
+            Payload::Read(&mut buf),
+        )
+        .await?;
+        Ok(self.settings.identify(&buf)?)
+    }
+
+    /// Resets the device and waits until it is ready again.
+    ///
+    /// Afterwards the device is without 32 byte wrapping, and the driver state
+    /// is changed to match.
+    pub async fn reset<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<(), DeviceError<SPI::Error>> {
+        self.command(Opcode::ResetEnable).await?;
+        self.command(Opcode::Reset).await?;
+        delay.delay_us(RESET_US).await;
+        self.settings.reset_done();
+        Ok(())
+    }
+
+    /// Brings the device back into a known state after a bus error.
+    ///
+    /// Resets the device and then restores the burst length the driver had before.
+    pub async fn recover<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<(), DeviceError<SPI::Error>> {
+        let burst = self.settings.burst_length;
+        self.reset(delay).await?;
+        self.set_burst(burst).await
+    }
+
+    /// Changes the burst length, toggling the wrap boundary of the device if needed.
+    pub async fn set_burst(&mut self, burst: BurstLength) -> Result<(), DeviceError<SPI::Error>> {
+        if self.settings.burst_toggle(burst)? {
+            self.command(Opcode::SetBurstLength).await?;
+        }
+
+        self.settings.burst_length = burst;
+        Ok(())
+    }
+
+    /// Reads `buf.len()` bytes starting at `address`.
+    pub async fn read(
+        &mut self,
+        address: u32,
+        buf: &mut [u8],
+    ) -> Result<(), DeviceError<SPI::Error>> {
+        let phases = self.settings.freq.read_phases();
+        let plan = self.settings.plan(phases, address, buf.len())?;
+        self.run(phases, plan, Payload::Read(buf)).await
+    }
+
+    /// Writes `buf` starting at `address`.
+    pub async fn write(&mut self, address: u32, buf: &[u8]) -> Result<(), DeviceError<SPI::Error>> {
+        let phases = Phases::single(Opcode::Write, 0);
+        let plan = self.settings.plan(phases, address, buf.len())?;
+        self.run(phases, plan, Payload::Write(buf)).await
+    }
+
+    /// Reads the whole 32 byte line containing `address`, starting at `address`.
+    /// See `PSRAM::read_wrapped`.
+    pub async fn read_wrapped(
+        &mut self,
+        address: u32,
+        buf: &mut [u8; WRAP_SIZE],
+    ) -> Result<(), DeviceError<SPI::Error>> {
+        let phases = self.settings.freq.read_phases();
+        let plan = self.settings.wrapped(phases, address)?;
+        self.run(phases, plan, Payload::Read(buf)).await
+    }
+
+    /// Writes the whole 32 byte line containing `address`, starting at `address`.
+    /// See `PSRAM::write_wrapped`.
+    pub async fn write_wrapped(
+        &mut self,
+        address: u32,
+        buf: &[u8; WRAP_SIZE],
+    ) -> Result<(), DeviceError<SPI::Error>> {
+        let phases = Phases::single(Opcode::Write, 0);
+        let plan = self.settings.wrapped(phases, address)?;
+        self.run(phases, plan, Payload::Write(buf)).await
+    }
+
+    async fn command(&mut self, opcode: Opcode) -> Result<(), DeviceError<SPI::Error>> {
+        self.spi
+            .write(&[opcode as u8])
+            .await
+            .map_err(DeviceError::Spi)
+    }
+
+    /// Runs the planned transactions, each carrying its part of `payload`.
+    async fn run(
+        &mut self,
+        phases: Phases,
+        plan: impl Iterator<Item = Transaction>,
+        mut payload: Payload<'_>,
+    ) -> Result<(), DeviceError<SPI::Error>> {
+        for transaction in plan {
+            self.transaction(
+                phases,
+                transaction.address,
+                payload.slice(transaction.range),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Runs one command, address, wait and data sequence as a single transaction.
+    async fn transaction(
+        &mut self,
+        phases: Phases,
+        address: u32,
+        payload: Payload<'_>,
+    ) -> Result<(), DeviceError<SPI::Error>> {
+        let header = Header::new(phases, address);
+        self.spi
+            .transaction(&mut header.operations(payload))
+            .await
+            .map_err(DeviceError::Spi)
+    }
+}
+
+#[cfg(feature = "embedded-storage-async")]
+impl<E: core::fmt::Debug> NorFlashError for DeviceError<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            DeviceError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-storage-async")]
+impl<SPI: SpiDevice> ErrorType for PsramDevice<SPI> {
+    type Error = DeviceError<SPI::Error>;
+}
+
+/// PSRAM has no erase or write granularity, so every size is one byte.
+#[cfg(feature = "embedded-storage-async")]
+impl<SPI: SpiDevice> ReadNorFlash for PsramDevice<SPI> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        PsramDevice::read(self, offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.settings.device.capacity as usize
+    }
+}
+
+/// Erasing fills the range with 0xFF, like an erased flash.
+#[cfg(feature = "embedded-storage-async")]
+impl<SPI: SpiDevice> NorFlash for PsramDevice<SPI> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = 1;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to {
+            return Err(DeviceError::OutOfBounds);
+        }
+        self.settings.check_bounds(from, (to - from) as usize)?;
+
+        let erased = [0xFF; 64];
+        let mut address = from;
+        while address < to {
+            let len = erased.len().min((to - address) as usize);
+            PsramDevice::write(self, address, &erased[..len]).await?;
+            address += len as u32;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        PsramDevice::write(self, offset, bytes).await
+    }
+}
@@ -0,0 +1,114 @@
+use crate::heap::{AllocError, Heap, PsramAlloc};
+use crate::psram::PSRAM;
+use crate::Error;
+
+use bytemuck::Pod;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::OutputPin;
+
+/// A value of type `T` stored on the device.
+///
+/// `T` has to be plain old data, so it can be copied to and from the device
+/// byte by byte without any unsafe code. Every access goes over the bus, the
+/// value is never cached in MCU RAM.
+///
+/// ```
+/// # #[cfg(feature = "sim")]
+/// # fn main() {
+/// # use esp_psram::psram::{BurstLength, Freq, PSRAM};
+/// # use esp_psram::sim::Simulator;
+/// # let sim = Simulator::new(Freq::EightyFour);
+/// # let mut psram = PSRAM::init(sim.spi(), sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+/// # let mut heap: esp_psram::heap::Heap<8> = esp_psram::heap::Heap::new(0, psram.capacity());
+/// # #[derive(Clone, Copy, Default)]
+/// # #[repr(C)]
+/// # struct Config {
+/// #     boots: u32,
+/// # }
+/// # unsafe impl bytemuck::Zeroable for Config {}
+/// # unsafe impl bytemuck::Pod for Config {}
+/// use esp_psram::boxed::PsramBox;
+///
+/// let mut config: PsramBox<Config> = PsramBox::allocate(&mut heap).unwrap();
+/// config.store(&mut psram, &Config::default()).unwrap();
+/// config.update(&mut psram, |config| config.boots += 1).unwrap();
+/// assert_eq!(config.load(&mut psram).unwrap().boots, 1);
+/// heap.free(config.into_region()).unwrap();
+/// # }
+/// # #[cfg(not(feature = "sim"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct PsramBox<T: Pod> {
+    region: PsramAlloc,
+    _value: PhantomData<T>,
+}
+
+impl<T: Pod> PsramBox<T> {
+    /// Uses `region` to store the value.
+    ///
+    /// The region is given back if it is smaller than `T`. Its content is not
+    /// touched, so the box holds whatever the region held before.
+    pub fn new(region: PsramAlloc) -> Result<Self, PsramAlloc> {
+        if (region.len() as usize) < size_of::<T>() {
+            return Err(region);
+        }
+        Ok(PsramBox {
+            region,
+            _value: PhantomData,
+        })
+    }
+
+    /// Allocates a region for the value from `heap`. The content is not initialised.
+    pub fn allocate<const N: usize>(heap: &mut Heap<N>) -> Result<Self, AllocError> {
+        let region = heap.allocate(size_of::<T>() as u32)?;
+        Ok(PsramBox {
+            region,
+            _value: PhantomData,
+        })
+    }
+
+    /// Reads the value from the device.
+    pub fn load<SPI: Transfer<u8>, CS: OutputPin>(
+        &self,
+        psram: &mut PSRAM<SPI, CS>,
+    ) -> Result<T, Error<SPI, CS>> {
+        let mut value = T::zeroed();
+        self.region
+            .read(psram, 0, bytemuck::bytes_of_mut(&mut value))?;
+        Ok(value)
+    }
+
+    /// Writes `value` to the device.
+    pub fn store<SPI: Transfer<u8>, CS: OutputPin>(
+        &mut self,
+        psram: &mut PSRAM<SPI, CS>,
+        value: &T,
+    ) -> Result<(), Error<SPI, CS>> {
+        self.region.write(psram, 0, bytemuck::bytes_of(value))
+    }
+
+    /// Loads the value, changes it with `f` and stores it again. Returns the new value.
+    pub fn update<SPI: Transfer<u8>, CS: OutputPin>(
+        &mut self,
+        psram: &mut PSRAM<SPI, CS>,
+        f: impl FnOnce(&mut T),
+    ) -> Result<T, Error<SPI, CS>> {
+        let mut value = self.load(psram)?;
+        f(&mut value);
+        self.store(psram, &value)?;
+        Ok(value)
+    }
+
+    /// The region the value is stored in.
+    pub fn region(&self) -> &PsramAlloc {
+        &self.region
+    }
+
+    /// Gives up the box and returns its region, for example to free it.
+    pub fn into_region(self) -> PsramAlloc {
+        self.region
+    }
+}
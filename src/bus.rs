@@ -0,0 +1,189 @@
+use crate::psram::Freq;
+
+use core::cell::RefCell;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::OutputPin;
+
+/// A SPI master that can also clock data on all four IO lanes (SIO0-SIO3).
+///
+/// Needed for the QPI mode of the device. Each byte takes two clocks on the
+/// quad lanes, high nibble first. The chip select line is still driven by the
+/// driver, so implementations must not touch it.
+pub trait QuadTransfer<W>: Transfer<W> {
+    /// Sends `words` over the four lanes, ignoring anything driven back.
+    fn try_quad_write(&mut self, words: &[W]) -> Result<(), Self::Error>;
+
+    /// Clocks in `words.len()` words over the four lanes, overwriting `words`.
+    fn try_quad_read(&mut self, words: &mut [W]) -> Result<(), Self::Error>;
+}
+
+/// A SPI master whose clock can be changed at runtime.
+///
+/// Needed for `calibrate`. Masters which can shift the point where they sample
+/// MISO can also expose that, so calibration can search over it.
+pub trait ClockControl: Transfer<u8> {
+    /// Switches the SPI clock to `freq`, or the fastest clock the master can
+    /// produce that does not exceed it.
+    ///
+    /// Returns the clock that was set in MHz, rounded down. The driver sizes
+    /// its transactions for tCEM from it, see `PSRAM::set_clock_mhz`.
+    fn try_set_freq(&mut self, freq: Freq) -> Result<u32, Self::Error>;
+
+    /// Number of sample delay settings. Defaults to a single one.
+    fn sample_delays(&self) -> u8 {
+        1
+    }
+
+    /// The selected sample delay. Defaults to 0, the only one there is by default.
+    fn sample_delay(&self) -> u8 {
+        0
+    }
+
+    /// Selects the sample delay, from 0 up to `sample_delays()`.
+    fn try_set_sample_delay(&mut self, _delay: u8) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Holds CS low while it lives.
+///
+/// CS is released by `release`, or on drop if the guard goes out of scope
+/// early, so an error or panic can't leave the device selected.
+pub(crate) struct ChipSelect<'a, CS: OutputPin> {
+    cs: &'a mut CS,
+    released: bool,
+}
+
+impl<'a, CS: OutputPin> ChipSelect<'a, CS> {
+    /// Drives CS low.
+    pub(crate) fn select(cs: &'a mut CS) -> Result<Self, CS::Error> {
+        cs.try_set_low()?;
+        Ok(ChipSelect {
+            cs,
+            released: false,
+        })
+    }
+
+    /// Drives CS high. If that fails, it is tried once more on drop.
+    pub(crate) fn release(mut self) -> Result<(), CS::Error> {
+        self.cs.try_set_high()?;
+        self.released = true;
+        Ok(())
+    }
+}
+
+impl<'a, CS: OutputPin> Drop for ChipSelect<'a, CS> {
+    fn drop(&mut self) {
+        if !self.released {
+            // Nothing to report the error to
+            let _ = self.cs.try_set_high();
+        }
+    }
+}
+
+/// A SPI master shared by several devices, each with its own chip select line.
+///
+/// Every device gets a `BusProxy` from `acquire` and its own `PSRAM` driver,
+/// so each chip keeps its own frequency, burst length and mode:
+///
+/// ```
+/// # #[cfg(feature = "sim")]
+/// # fn main() {
+/// # use esp_psram::psram::{BurstLength, Freq, PSRAM};
+/// # use esp_psram::sim::{SimBus, Simulator};
+/// # let (chip0, chip1) = (Simulator::new(Freq::EightyFour), Simulator::new(Freq::EightyFour));
+/// # let (spi, cs0, cs1) = (SimBus::new(&[&chip0, &chip1]), chip0.cs(), chip1.cs());
+/// use esp_psram::bus::SharedBus;
+///
+/// let bus = SharedBus::new(spi);
+/// let mut first = PSRAM::init(bus.acquire(), cs0, Freq::EightyFour, BurstLength::OneKByte).unwrap();
+/// let mut second = PSRAM::init(bus.acquire(), cs1, Freq::EightyFour, BurstLength::ThirtyTwoByte).unwrap();
+/// # assert!(!chip0.wrap() && chip1.wrap());
+/// # first.read_id().unwrap();
+/// # second.read_id().unwrap();
+/// # }
+/// # #[cfg(not(feature = "sim"))]
+/// # fn main() {}
+/// ```
+///
+/// The bus is only borrowed for a single transfer. The drivers are not `Sync`,
+/// so a transaction on one chip always completes before another chip is selected.
+///
+/// The `embedded-hal` 1.0 driver does not need this, use the `SpiDevice`
+/// implementations from `embedded-hal-bus` instead.
+#[derive(Debug)]
+pub struct SharedBus<SPI> {
+    bus: RefCell<SPI>,
+}
+
+impl<SPI> SharedBus<SPI> {
+    /// Wraps a SPI master so it can be shared.
+    pub fn new(spi: SPI) -> Self {
+        Self {
+            bus: RefCell::new(spi),
+        }
+    }
+
+    /// Creates a proxy to hand to the driver of one device.
+    pub fn acquire(&self) -> BusProxy<'_, SPI> {
+        BusProxy { bus: &self.bus }
+    }
+
+    /// Returns the SPI master. All proxies must have been dropped.
+    pub fn into_inner(self) -> SPI {
+        self.bus.into_inner()
+    }
+}
+
+/// One device's handle on a `SharedBus`.
+///
+/// Implements the same SPI traits as the underlying master.
+#[derive(Debug)]
+pub struct BusProxy<'a, SPI> {
+    bus: &'a RefCell<SPI>,
+}
+
+impl<'a, W, SPI: Transfer<W>> Transfer<W> for BusProxy<'a, SPI> {
+    type Error = SPI::Error;
+
+    fn try_transfer<'w>(&mut self, words: &'w mut [W]) -> Result<&'w [W], Self::Error> {
+        self.bus.borrow_mut().try_transfer(words)?;
+        Ok(words)
+    }
+}
+
+impl<'a, W, SPI: Write<W>> Write<W> for BusProxy<'a, SPI> {
+    type Error = SPI::Error;
+
+    fn try_write(&mut self, words: &[W]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().try_write(words)
+    }
+}
+
+impl<'a, SPI: ClockControl> ClockControl for BusProxy<'a, SPI> {
+    fn try_set_freq(&mut self, freq: Freq) -> Result<u32, Self::Error> {
+        self.bus.borrow_mut().try_set_freq(freq)
+    }
+
+    fn sample_delays(&self) -> u8 {
+        self.bus.borrow().sample_delays()
+    }
+
+    fn sample_delay(&self) -> u8 {
+        self.bus.borrow().sample_delay()
+    }
+
+    fn try_set_sample_delay(&mut self, delay: u8) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().try_set_sample_delay(delay)
+    }
+}
+
+impl<'a, W, SPI: QuadTransfer<W>> QuadTransfer<W> for BusProxy<'a, SPI> {
+    fn try_quad_write(&mut self, words: &[W]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().try_quad_write(words)
+    }
+
+    fn try_quad_read(&mut self, words: &mut [W]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().try_quad_read(words)
+    }
+}
@@ -0,0 +1,160 @@
+use crate::bus::ClockControl;
+use crate::psram::{Freq, Mode, PSRAM};
+use crate::Error;
+
+use embedded_hal::digital::OutputPin;
+
+/// All frequency steps, slowest first.
+const STEPS: [Freq; 5] = [
+    Freq::ThreeThree,
+    Freq::EightyFour,
+    Freq::OneZeroFour,
+    Freq::OneThreeThree,
+    Freq::OneFourFour,
+];
+
+/// Fixed patterns written before the pseudo random one.
+const PATTERNS: [u8; 4] = [0x00, 0xFF, 0xAA, 0x55];
+
+/// A bus setting found by `calibrate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Setting {
+    /// The clock frequency.
+    pub freq: Freq,
+    /// The sample delay of the SPI master, see `ClockControl::sample_delays`.
+    pub sample_delay: u8,
+}
+
+/// Finds the fastest bus setting which reads back what was written.
+///
+/// Tries every `Freq` step the device and burst length allow, slowest first,
+/// until one fails. At each step every sample delay is tested by writing and
+/// reading back several patterns, each filling `buf`, at `address`. The memory
+/// there is overwritten. Of the delays which pass, the one in the middle of
+/// the first passing window is used, as it has the most margin.
+///
+/// `buf` needs at least one byte, and nothing is tested with an empty one. A
+/// page or more also covers the longest bursts.
+///
+/// The fastest passing setting is applied to both the SPI master and the
+/// driver and returned, along with the clock the master reports to
+/// `PSRAM::set_clock_mhz`. If nothing passes or `buf` is empty, the clock and
+/// sample delay from before are restored and `None` is returned. Only
+/// available in SPI mode.
+pub fn calibrate<SPI: ClockControl, CS: OutputPin>(
+    psram: &mut PSRAM<SPI, CS>,
+    address: u32,
+    buf: &mut [u8],
+) -> Result<Option<Setting>, Error<SPI, CS>> {
+    if psram.mode() != Mode::Spi {
+        return Err(Error::InvalidMode);
+    }
+    // Nothing would be compared, so every step would pass
+    if buf.is_empty() {
+        return Ok(None);
+    }
+
+    let original = psram.freq();
+    let original_delay = psram.spi_mut().sample_delay();
+    let mut best = None;
+
+    for &freq in STEPS.iter() {
+        // Steps above the device or not allowed with the burst length are skipped
+        if psram.set_freq(freq).is_err() {
+            continue;
+        }
+        let mhz = psram.spi_mut().try_set_freq(freq).map_err(Error::Spi)?;
+        psram.set_clock_mhz(mhz);
+
+        match sample_window(psram, address, buf)? {
+            Some(sample_delay) => best = Some(Setting { freq, sample_delay }),
+            // Faster steps won't be any better
+            None => break,
+        }
+    }
+
+    let setting = match best {
+        Some(setting) => setting,
+        None => {
+            psram.set_freq(original)?;
+            let spi = psram.spi_mut();
+            let mhz = spi.try_set_freq(original).map_err(Error::Spi)?;
+            spi.try_set_sample_delay(original_delay)
+                .map_err(Error::Spi)?;
+            psram.set_clock_mhz(mhz);
+            return Ok(None);
+        }
+    };
+
+    psram.set_freq(setting.freq)?;
+    let spi = psram.spi_mut();
+    let mhz = spi.try_set_freq(setting.freq).map_err(Error::Spi)?;
+    spi.try_set_sample_delay(setting.sample_delay)
+        .map_err(Error::Spi)?;
+    psram.set_clock_mhz(mhz);
+    Ok(Some(setting))
+}
+
+/// Tests every sample delay at the current clock. Returns the middle of the
+/// first window of passing delays.
+fn sample_window<SPI: ClockControl, CS: OutputPin>(
+    psram: &mut PSRAM<SPI, CS>,
+    address: u32,
+    buf: &mut [u8],
+) -> Result<Option<u8>, Error<SPI, CS>> {
+    let mut window: Option<(u8, u8)> = None;
+
+    for delay in 0..psram.spi_mut().sample_delays() {
+        psram
+            .spi_mut()
+            .try_set_sample_delay(delay)
+            .map_err(Error::Spi)?;
+
+        if reliable(psram, address, buf)? {
+            window = Some(match window {
+                Some((first, _)) => (first, delay),
+                None => (delay, delay),
+            });
+        } else if window.is_some() {
+            break;
+        }
+    }
+
+    Ok(window.map(|(first, last)| first + (last - first) / 2))
+}
+
+/// Writes and reads back each pattern.
+fn reliable<SPI: ClockControl, CS: OutputPin>(
+    psram: &mut PSRAM<SPI, CS>,
+    address: u32,
+    buf: &mut [u8],
+) -> Result<bool, Error<SPI, CS>> {
+    for pattern in 0..=PATTERNS.len() {
+        let expected = |index: usize| match PATTERNS.get(pattern) {
+            Some(byte) => *byte,
+            None => noise(index),
+        };
+
+        for (index, byte) in buf.iter_mut().enumerate() {
+            *byte = expected(index);
+        }
+        psram.write_slice(address, buf)?;
+
+        buf.iter_mut().for_each(|byte| *byte = !*byte);
+        psram.read_slice(address, buf)?;
+        if buf
+            .iter()
+            .enumerate()
+            .any(|(index, byte)| *byte != expected(index))
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Pseudo random byte for `index`, so neighbouring bytes toggle many lines.
+fn noise(index: usize) -> u8 {
+    let x = (index as u32).wrapping_mul(0x9E37_79B9);
+    (x >> 24) as u8 ^ x as u8
+}
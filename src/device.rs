@@ -0,0 +1,139 @@
+use crate::psram::{Density, Identification, Variant, TCEM_NS};
+
+/// Optional commands a device supports on top of Read, Write, Read ID and Reset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Commands {
+    /// Fast Read (0x0B) with wait cycles.
+    pub fast_read: bool,
+    /// Fast Read Quad (0xEB), Quad Write (0x38) and QPI mode.
+    pub quad: bool,
+    /// Wrap boundary toggle (0xC0) for 32 byte wrapped bursts.
+    pub wrap: bool,
+}
+
+/// All optional commands.
+const ALL_COMMANDS: Commands = Commands {
+    fast_read: true,
+    quad: true,
+    wrap: true,
+};
+
+/// Describes a SPI pseudo SRAM the driver can talk to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Device {
+    /// Part name, for diagnostics only.
+    pub name: &'static str,
+    /// Manufacturer ID reported by Read ID.
+    pub manufacturer_id: u8,
+    /// Density reported by Read ID. `None` matches any density.
+    pub density: Option<Density>,
+    /// Size of the memory array in bytes.
+    pub capacity: u32,
+    /// Page size in bytes, not zero. Bursts above 84MHz can't cross a page.
+    pub page_size: u32,
+    /// Highest clock frequency in MHz.
+    pub max_mhz: u32,
+    /// Maximum time CS may stay low (tCEM) in nanoseconds.
+    pub tcem_ns: u32,
+    /// Optional commands the device supports.
+    pub commands: Commands,
+    /// The ESP-PSRAM64 variant, for Espressif parts.
+    pub variant: Option<Variant>,
+}
+
+impl Device {
+    /// Whether a Read ID response belongs to this device.
+    pub fn matches(&self, id: &Identification) -> bool {
+        let density = match self.density {
+            Some(density) => density == id.density,
+            None => true,
+        };
+        id.manufacturer_id == self.manufacturer_id && density
+    }
+
+    /// Finds the first entry of `DEVICES` matching a Read ID response.
+    pub fn lookup(id: &Identification) -> Option<&'static Device> {
+        DEVICES.iter().find(|device| device.matches(id))
+    }
+}
+
+/// Espressif ESP-PSRAM64, 64Mbit at 1.8V.
+pub const ESP_PSRAM64: Device = Device {
+    name: "ESP-PSRAM64",
+    manufacturer_id: 0x0D,
+    density: Some(Density::Mbit64),
+    capacity: 8 * 1024 * 1024,
+    page_size: 1024,
+    max_mhz: Variant::EspPsram64.max_freq().mhz(),
+    tcem_ns: TCEM_NS,
+    commands: ALL_COMMANDS,
+    variant: Some(Variant::EspPsram64),
+};
+
+/// Espressif ESP-PSRAM64H, 64Mbit at 3.3V.
+///
+/// Reports the same ID as the ESP-PSRAM64, so the part has to come from the
+/// board design. The driver starts out with `ESP_PSRAM64`, which `read_id`
+/// keeps; pass this to `PSRAM::set_device` to limit the clock to 133MHz.
+pub const ESP_PSRAM64H: Device = Device {
+    name: "ESP-PSRAM64H",
+    max_mhz: Variant::EspPsram64H.max_freq().mhz(),
+    variant: Some(Variant::EspPsram64H),
+    ..ESP_PSRAM64
+};
+
+/// AP Memory APS1604M, 16Mbit.
+pub const APS1604M: Device = Device {
+    name: "APS1604M",
+    density: Some(Density::Mbit16),
+    capacity: 2 * 1024 * 1024,
+    max_mhz: 133,
+    variant: None,
+    ..ESP_PSRAM64
+};
+
+/// AP Memory APS6404L, 64Mbit.
+pub const APS6404L: Device = Device {
+    name: "APS6404L",
+    max_mhz: 133,
+    variant: None,
+    ..ESP_PSRAM64
+};
+
+/// AP Memory 128Mbit quad parts.
+pub const APS12804: Device = Device {
+    name: "APS12804",
+    density: Some(Density::Mbit128),
+    capacity: 16 * 1024 * 1024,
+    max_mhz: 133,
+    variant: None,
+    ..ESP_PSRAM64
+};
+
+/// Lyontek LY68L6400, 64Mbit. Reports the same ID as the AP Memory parts.
+pub const LY68L6400: Device = Device {
+    name: "LY68L6400",
+    max_mhz: 133,
+    variant: None,
+    ..ESP_PSRAM64
+};
+
+/// ISSI IS66WVS4M8, 32Mbit.
+pub const IS66WVS4M8: Device = Device {
+    name: "IS66WVS4M8",
+    manufacturer_id: 0x9D,
+    density: None,
+    capacity: 4 * 1024 * 1024,
+    page_size: 1024,
+    max_mhz: 104,
+    tcem_ns: TCEM_NS,
+    commands: ALL_COMMANDS,
+    variant: None,
+};
+
+/// Devices `PSRAM::read_id` picks from, first match wins.
+///
+/// Parts that report the same ID share a descriptor with the same limits, the
+/// most conservative one is listed first. Parts missing here can be passed to
+/// `PSRAM::set_device`.
+pub const DEVICES: &[Device] = &[ESP_PSRAM64H, APS1604M, APS12804, IS66WVS4M8];
@@ -24,6 +24,12 @@ pub enum Error<SPI: Transfer<u8>, GPIO: OutputPin> {
     /// Device does not support the mode of operation selected
     InvalidMode,
 
+    /// A delay could not be performed.
+    Delay,
+
+    /// The access lies outside of the device.
+    OutOfBounds,
+
     #[doc(hidden)]
     __NonExhaustive(private::Private),
 }
@@ -39,6 +45,8 @@ where
             Error::Gpio(gpio) => write!(f, "Error::Gpio({:?})", gpio),
             Error::InvalidDevice => f.write_str("Error::InvalidDevice"),
             Error::InvalidMode => f.write_str("Error::InvalidMode"),
+            Error::Delay => f.write_str("Error::Delay"),
+            Error::OutOfBounds => f.write_str("Error::OutOfBounds"),
             Error::__NonExhaustive(_) => unreachable!(),
         }
     }
@@ -57,7 +65,71 @@ where
                 f.write_str("This is not the correct device for the driver or it is faulty")
             }
             Error::InvalidMode => f.write_str("The driver or device is not in the correct mode"),
+            Error::Delay => f.write_str("Delay error"),
+            Error::OutOfBounds => f.write_str("The access lies outside of the device"),
             Error::__NonExhaustive(_) => unreachable!(),
         }
     }
 }
+
+/// The error type used by the drivers built on `SpiDevice`.
+///
+/// The chip select line is driven by the `SpiDevice`, so there is no GPIO
+/// error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceError<E> {
+    /// An SPI transaction failed.
+    Spi(E),
+
+    /// Device is not the correct type.
+    InvalidDevice,
+
+    /// Device does not support the mode of operation selected
+    InvalidMode,
+
+    /// The access lies outside of the device.
+    OutOfBounds,
+}
+
+impl<E: Display> Display for DeviceError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::Spi(spi) => write!(f, "SPI error: {}", spi),
+            DeviceError::InvalidDevice => {
+                f.write_str("This is not the correct device for the driver or it is faulty")
+            }
+            DeviceError::InvalidMode => {
+                f.write_str("The driver or device is not in the correct mode")
+            }
+            DeviceError::OutOfBounds => f.write_str("The access lies outside of the device"),
+        }
+    }
+}
+
+impl<E> From<Invalid> for DeviceError<E> {
+    fn from(invalid: Invalid) -> Self {
+        match invalid {
+            Invalid::Device => DeviceError::InvalidDevice,
+            Invalid::Mode => DeviceError::InvalidMode,
+            Invalid::OutOfBounds => DeviceError::OutOfBounds,
+        }
+    }
+}
+
+/// Protocol errors found by the state shared between the drivers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Invalid {
+    Device,
+    Mode,
+    OutOfBounds,
+}
+
+impl<SPI: Transfer<u8>, GPIO: OutputPin> From<Invalid> for Error<SPI, GPIO> {
+    fn from(invalid: Invalid) -> Self {
+        match invalid {
+            Invalid::Device => Error::InvalidDevice,
+            Invalid::Mode => Error::InvalidMode,
+            Invalid::OutOfBounds => Error::OutOfBounds,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::bus::QuadTransfer;
+
+use core::cell::Cell;
+use core::fmt::{self, Display};
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::OutputPin;
+
+/// Error of the fault injecting wrappers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault<E> {
+    /// The operation was failed on purpose and not passed on.
+    Injected,
+    /// The wrapped SPI master or pin failed.
+    Inner(E),
+}
+
+impl<E: Display> Display for Fault<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Injected => f.write_str("Injected fault"),
+            Fault::Inner(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Fails a chosen operation on a SPI master and CS pin, to exercise error paths.
+///
+/// Every SPI transfer and pin change through the wrappers from `spi` and `cs`
+/// counts as one operation. Sweeping the failing operation over a whole call
+/// checks each of its error paths:
+///
+/// ```
+/// # use embedded_hal::storage::{Address, MultiRead};
+/// # use esp_psram::psram::{BurstLength, Freq, PSRAM};
+/// # use esp_psram::sim::Simulator;
+/// # let sim = Simulator::new(Freq::EightyFour);
+/// # let (spi, cs) = (sim.spi(), sim.cs());
+/// # let mut buf = [0; 64];
+/// use esp_psram::fault::Faults;
+///
+/// let faults = Faults::new();
+/// let mut psram = PSRAM::init(faults.spi(spi), faults.cs(cs), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+/// for n in 0.. {
+///     faults.fail_at(n);
+///     let result = psram.try_read_slice(Address(0), &mut buf);
+///     assert!(faults.idle());
+///     if result.is_ok() {
+///         break;
+///     }
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Faults {
+    operations: Cell<usize>,
+    fail_at: Cell<Option<usize>>,
+    selected: Cell<bool>,
+}
+
+impl Faults {
+    /// Creates a counter which fails nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails operation `n`, counting from zero, and restarts the count.
+    ///
+    /// Only that single operation fails, later ones are passed on again.
+    pub fn fail_at(&self, n: usize) {
+        self.operations.set(0);
+        self.fail_at.set(Some(n));
+    }
+
+    /// Stops failing operations.
+    pub fn disarm(&self) {
+        self.fail_at.set(None);
+    }
+
+    /// The number of operations since the last `fail_at`, or since creation.
+    pub fn operations(&self) -> usize {
+        self.operations.get()
+    }
+
+    /// Whether CS was last driven high, i.e. the bus is idle. True before the first change.
+    pub fn idle(&self) -> bool {
+        !self.selected.get()
+    }
+
+    /// Wraps the SPI master.
+    pub fn spi<SPI>(&self, spi: SPI) -> FaultySpi<'_, SPI> {
+        FaultySpi { spi, faults: self }
+    }
+
+    /// Wraps the CS pin.
+    pub fn cs<CS>(&self, cs: CS) -> FaultyCs<'_, CS> {
+        FaultyCs { cs, faults: self }
+    }
+
+    /// Counts an operation. Returns an error if it has to fail.
+    fn operation<E>(&self) -> Result<(), Fault<E>> {
+        let n = self.operations.get();
+        self.operations.set(n + 1);
+        if self.fail_at.get() == Some(n) {
+            return Err(Fault::Injected);
+        }
+        Ok(())
+    }
+}
+
+/// The SPI master of `Faults`.
+#[derive(Debug)]
+pub struct FaultySpi<'a, SPI> {
+    spi: SPI,
+    faults: &'a Faults,
+}
+
+impl<'a, SPI> FaultySpi<'a, SPI> {
+    /// Returns the wrapped SPI master.
+    pub fn into_inner(self) -> SPI {
+        self.spi
+    }
+}
+
+impl<'a, SPI: Transfer<u8>> Transfer<u8> for FaultySpi<'a, SPI> {
+    type Error = Fault<SPI::Error>;
+
+    fn try_transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        self.faults.operation()?;
+        self.spi.try_transfer(words).map_err(Fault::Inner)
+    }
+}
+
+impl<'a, SPI: Write<u8>> Write<u8> for FaultySpi<'a, SPI> {
+    type Error = Fault<SPI::Error>;
+
+    fn try_write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.faults.operation()?;
+        self.spi.try_write(words).map_err(Fault::Inner)
+    }
+}
+
+impl<'a, SPI: QuadTransfer<u8>> QuadTransfer<u8> for FaultySpi<'a, SPI> {
+    fn try_quad_write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.faults.operation()?;
+        self.spi.try_quad_write(words).map_err(Fault::Inner)
+    }
+
+    fn try_quad_read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.faults.operation()?;
+        self.spi.try_quad_read(words).map_err(Fault::Inner)
+    }
+}
+
+/// The CS pin of `Faults`. A failed change leaves the pin as it was.
+#[derive(Debug)]
+pub struct FaultyCs<'a, CS> {
+    cs: CS,
+    faults: &'a Faults,
+}
+
+impl<'a, CS> FaultyCs<'a, CS> {
+    /// Returns the wrapped CS pin.
+    pub fn into_inner(self) -> CS {
+        self.cs
+    }
+}
+
+impl<'a, CS: OutputPin> OutputPin for FaultyCs<'a, CS> {
+    type Error = Fault<CS::Error>;
+
+    fn try_set_low(&mut self) -> Result<(), Self::Error> {
+        self.faults.operation()?;
+        self.cs.try_set_low().map_err(Fault::Inner)?;
+        self.faults.selected.set(true);
+        Ok(())
+    }
+
+    fn try_set_high(&mut self) -> Result<(), Self::Error> {
+        self.faults.operation()?;
+        self.cs.try_set_high().map_err(Fault::Inner)?;
+        self.faults.selected.set(false);
+        Ok(())
+    }
+}
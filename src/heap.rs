@@ -0,0 +1,294 @@
+use crate::psram::PSRAM;
+use crate::Error;
+
+use core::fmt::{self, Display};
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::OutputPin;
+
+/// A region of the device handed out by a `Heap`.
+///
+/// Not `Clone`, so a region can only be freed once. Reads and writes through
+/// the handle can't reach outside of the region.
+#[derive(Debug, PartialEq)]
+pub struct PsramAlloc {
+    offset: u32,
+    len: u32,
+}
+
+impl PsramAlloc {
+    /// Address of the first byte on the device.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Size of the region in bytes.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Whether the region is empty. Never true for a region from `Heap::allocate`.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads `buf.len()` bytes starting `offset` bytes into the region.
+    ///
+    /// Returns `Error::OutOfBounds` if that reaches past the end of the region.
+    pub fn read<SPI: Transfer<u8>, CS: OutputPin>(
+        &self,
+        psram: &mut PSRAM<SPI, CS>,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<(), Error<SPI, CS>> {
+        let address = self.address(offset, buf.len())?;
+        psram.read_slice(address, buf)
+    }
+
+    /// Writes `buf` starting `offset` bytes into the region.
+    ///
+    /// Returns `Error::OutOfBounds` if that reaches past the end of the region.
+    pub fn write<SPI: Transfer<u8>, CS: OutputPin>(
+        &self,
+        psram: &mut PSRAM<SPI, CS>,
+        offset: u32,
+        buf: &[u8],
+    ) -> Result<(), Error<SPI, CS>> {
+        let address = self.address(offset, buf.len())?;
+        psram.write_slice(address, buf)
+    }
+
+    /// The device address of `len` bytes at `offset`, if they lie within the region.
+    fn address<SPI: Transfer<u8>, CS: OutputPin>(
+        &self,
+        offset: u32,
+        len: usize,
+    ) -> Result<u32, Error<SPI, CS>> {
+        let end = u64::from(offset) + len as u64;
+        if end > u64::from(self.len) {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(self.offset + offset)
+    }
+}
+
+/// Reasons an allocation failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllocError {
+    /// No free block is large enough.
+    OutOfMemory,
+    /// The block table is full, so a free block can't be split.
+    TableFull,
+    /// Zero byte allocations are not supported.
+    ZeroSize,
+}
+
+impl Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocError::OutOfMemory => f.write_str("No free block is large enough"),
+            AllocError::TableFull => f.write_str("The block table is full"),
+            AllocError::ZeroSize => f.write_str("Zero byte allocation"),
+        }
+    }
+}
+
+/// Usage and fragmentation of a `Heap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    /// Bytes handed out.
+    pub used: u32,
+    /// Bytes free.
+    pub free: u32,
+    /// The largest allocation that would currently succeed.
+    pub largest_free: u32,
+    /// Number of allocations.
+    pub used_blocks: usize,
+    /// Number of free blocks.
+    pub free_blocks: usize,
+}
+
+impl Stats {
+    /// Share of the free memory that is not part of the largest free block, in percent.
+    ///
+    /// 0 means all free memory is in one piece.
+    pub fn fragmentation(&self) -> u8 {
+        if self.free == 0 {
+            return 0;
+        }
+        (u64::from(self.free - self.largest_free) * 100 / u64::from(self.free)) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    offset: u32,
+    len: u32,
+    used: bool,
+}
+
+/// Hands out regions of the device, keeping all bookkeeping in MCU RAM.
+///
+/// The device is not memory mapped, so this can't be a global allocator.
+/// Instead each allocation is a `PsramAlloc` handle, read and written
+/// through the driver. The blocks are kept in a table of `N` entries, sorted
+/// by address; every allocation and every free gap takes one entry.
+///
+/// Allocation picks the smallest free block that fits. Freeing merges the
+/// block with free neighbours.
+///
+/// ```
+/// # #[cfg(feature = "sim")]
+/// # fn main() {
+/// # use esp_psram::psram::{BurstLength, Freq, PSRAM};
+/// # use esp_psram::sim::Simulator;
+/// # let sim = Simulator::new(Freq::EightyFour);
+/// # let mut psram = PSRAM::init(sim.spi(), sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+/// # let data = [0x5A; 4096];
+/// use esp_psram::heap::Heap;
+///
+/// let mut heap: Heap<64> = Heap::new(0, psram.capacity());
+/// let samples = heap.allocate(4096).unwrap();
+/// samples.write(&mut psram, 0, &data).unwrap();
+/// heap.free(samples).unwrap();
+/// # }
+/// # #[cfg(not(feature = "sim"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct Heap<const N: usize> {
+    blocks: [Block; N],
+    count: usize,
+}
+
+impl<const N: usize> Heap<N> {
+    /// Creates a heap managing `len` bytes starting at `offset`.
+    ///
+    /// Use `PSRAM::capacity` or `StorageSize` to manage the whole device.
+    /// Panics if `offset + len` overflows `u32`.
+    pub fn new(offset: u32, len: u32) -> Self {
+        assert!(
+            offset.checked_add(len).is_some(),
+            "the heap must end within the 32 bit address space"
+        );
+        let mut blocks = [Block {
+            offset: 0,
+            len: 0,
+            used: false,
+        }; N];
+        let mut count = 0;
+        if N > 0 && len > 0 {
+            blocks[0] = Block {
+                offset,
+                len,
+                used: false,
+            };
+            count = 1;
+        }
+        Heap { blocks, count }
+    }
+
+    /// Allocates `len` bytes.
+    pub fn allocate(&mut self, len: u32) -> Result<PsramAlloc, AllocError> {
+        if len == 0 {
+            return Err(AllocError::ZeroSize);
+        }
+
+        let index = self
+            .blocks()
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| !block.used && block.len >= len)
+            .min_by_key(|(_, block)| block.len)
+            .map(|(index, _)| index)
+            .ok_or(AllocError::OutOfMemory)?;
+
+        let block = self.blocks[index];
+        if block.len > len {
+            // The rest of the block stays free
+            self.insert(
+                index + 1,
+                Block {
+                    offset: block.offset + len,
+                    len: block.len - len,
+                    used: false,
+                },
+            )?;
+        }
+        self.blocks[index] = Block {
+            offset: block.offset,
+            len,
+            used: true,
+        };
+
+        Ok(PsramAlloc {
+            offset: block.offset,
+            len,
+        })
+    }
+
+    /// Returns a region to the heap, merging it with free neighbours.
+    ///
+    /// The handle is given back if it belongs to another heap.
+    pub fn free(&mut self, alloc: PsramAlloc) -> Result<(), PsramAlloc> {
+        let index =
+            match self.blocks().iter().position(|block| {
+                block.used && block.offset == alloc.offset && block.len == alloc.len
+            }) {
+                Some(index) => index,
+                None => return Err(alloc),
+            };
+        self.blocks[index].used = false;
+
+        if index + 1 < self.count && !self.blocks[index + 1].used {
+            self.blocks[index].len += self.blocks[index + 1].len;
+            self.remove(index + 1);
+        }
+        if index > 0 && !self.blocks[index - 1].used {
+            self.blocks[index - 1].len += self.blocks[index].len;
+            self.remove(index);
+        }
+        Ok(())
+    }
+
+    /// Current usage and fragmentation.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            used: 0,
+            free: 0,
+            largest_free: 0,
+            used_blocks: 0,
+            free_blocks: 0,
+        };
+
+        for block in self.blocks() {
+            if block.used {
+                stats.used += block.len;
+                stats.used_blocks += 1;
+            } else {
+                stats.free += block.len;
+                stats.free_blocks += 1;
+                stats.largest_free = stats.largest_free.max(block.len);
+            }
+        }
+        stats
+    }
+
+    fn blocks(&self) -> &[Block] {
+        &self.blocks[..self.count]
+    }
+
+    fn insert(&mut self, index: usize, block: Block) -> Result<(), AllocError> {
+        if self.count == N {
+            return Err(AllocError::TableFull);
+        }
+        self.blocks.copy_within(index..self.count, index + 1);
+        self.blocks[index] = block;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.blocks.copy_within(index + 1..self.count, index);
+        self.count -= 1;
+    }
+}
@@ -10,13 +10,51 @@
 /// Note, however, that burst operations which cross page boundaries have a lower max input clock frequency at 84 MHz.
 /// Both of the PSRAM devices can be accessed via the Serial Peripheral Interface (SPI).
 ///
-/// Additionally, a Quad Peripheral Interface (QPI) is supported by the device if the application needs faster data rates. (Not yet implemented in this driver)
+/// Additionally, a Quad Peripheral Interface (QPI) is supported by the device if the application needs faster data rates.
+/// QPI is available when the SPI master implements [`bus::QuadTransfer`].
 ///
 /// The devices also support unlimited reads and writes to the memory array.
 ///
 extern crate embedded_hal as hal;
+#[cfg(feature = "sim")]
+extern crate std;
+/// Implements the driver on top of the `embedded-hal-async` `SpiDevice`
+#[cfg(feature = "async")]
+pub mod asynch;
+/// Typed values stored on the device
+#[cfg(feature = "bytemuck")]
+pub mod boxed;
+/// Bus traits needed for the faster transfer modes and sharing a bus between devices
+pub mod bus;
+/// Finds the fastest reliable bus clock
+pub mod calibrate;
+/// Descriptors of the supported devices
+pub mod device;
 mod error;
+/// SPI master and pin wrappers which fail on purpose, for testing error handling
+#[cfg(feature = "sim")]
+pub mod fault;
+/// Allocates regions of the device
+pub mod heap;
+/// Memory tests for checking the device and its wiring
+pub mod memtest;
+/// Splits accesses into transactions the device accepts
+pub mod plan;
 /// Implements the driver and the storage traits
 pub mod psram;
+/// A simulated device for testing the driver on the host
+#[cfg(feature = "sim")]
+pub mod sim;
+/// Implements the driver on top of the `embedded-hal` 1.0 `SpiDevice`
+#[cfg(feature = "eh1")]
+pub mod spi_device;
+/// Records and decodes the transactions sent to the device
+#[cfg(feature = "trace")]
+pub mod trace;
+/// Resumable transfers that move forward one transaction per poll
+pub mod transfer;
+/// Arrays whose elements are stored on the device
+#[cfg(feature = "bytemuck")]
+pub mod vec;
 
-pub use crate::error::Error;
+pub use crate::error::{DeviceError, Error};
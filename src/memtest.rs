@@ -0,0 +1,230 @@
+use crate::psram::PSRAM;
+use crate::Error;
+
+use core::convert::TryFrom;
+use core::ops::Range;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::OutputPin;
+
+/// A byte which did not read back as written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Failure {
+    /// Address of the byte.
+    pub address: u32,
+    /// The value the test wrote.
+    pub expected: u8,
+    /// The value read back.
+    pub actual: u8,
+}
+
+/// Result of a test: `Ok(None)` if it passed, `Ok(Some(failure))` for the
+/// first mismatch, or the error of the driver.
+pub type TestResult<SPI, CS> = Result<Option<Failure>, Error<SPI, CS>>;
+
+/// Runs all tests over `range` and stops at the first failure.
+///
+/// `buf` is used as scratch space for each burst, see `march_c_minus`.
+/// Nothing is touched if `range` is empty. Panics if `buf` is empty.
+pub fn run_all<SPI: Transfer<u8>, CS: OutputPin>(
+    psram: &mut PSRAM<SPI, CS>,
+    range: Range<u32>,
+    buf: &mut [u8],
+) -> TestResult<SPI, CS> {
+    if range.is_empty() {
+        return Ok(None);
+    }
+    if let Some(failure) = data_bus(psram, range.start)? {
+        return Ok(Some(failure));
+    }
+    if let Some(failure) = address_lines(psram, range.clone())? {
+        return Ok(Some(failure));
+    }
+    if let Some(failure) = checkerboard(psram, range.clone(), buf)? {
+        return Ok(Some(failure));
+    }
+    march_c_minus(psram, range, buf)
+}
+
+/// March C- over `range`, with 0x00 as background and 0xFF as its inverse.
+///
+/// The elements are ⇕(w0) ⇑(r0,w1) ⇑(r1,w0) ⇓(r0,w1) ⇓(r1,w0) ⇕(r0). The
+/// first and last element don't depend on the order, so they read and write
+/// `buf.len()` bytes at a time, which the driver splits into the largest
+/// bursts it allows; `buf` should be at least a page long. The four elements
+/// in between read, compare and write each byte before moving on to the next
+/// one in the element's address order, so a coupling fault between any two
+/// bytes is seen. That is two transactions per byte and element, which takes
+/// a while over the whole device. Panics if `buf` is empty.
+pub fn march_c_minus<SPI: Transfer<u8>, CS: OutputPin>(
+    psram: &mut PSRAM<SPI, CS>,
+    range: Range<u32>,
+    buf: &mut [u8],
+) -> TestResult<SPI, CS> {
+    // ⇕(w0)
+    for chunk in chunks(range.clone(), buf.len()) {
+        let buf = &mut buf[..chunk.len()];
+        buf.iter_mut().for_each(|byte| *byte = 0x00);
+        psram.write_slice(chunk.start, buf)?;
+    }
+
+    let elements = [
+        (false, 0x00, 0xFF),
+        (false, 0xFF, 0x00),
+        (true, 0x00, 0xFF),
+        (true, 0xFF, 0x00),
+    ];
+    let len = range.end.saturating_sub(range.start);
+    for &(descending, expected, value) in elements.iter() {
+        for index in 0..len {
+            let address = if descending {
+                range.end - 1 - index
+            } else {
+                range.start + index
+            };
+
+            let mut actual = [0];
+            psram.read_slice(address, &mut actual)?;
+            if actual[0] != expected {
+                return Ok(Some(Failure {
+                    address,
+                    expected,
+                    actual: actual[0],
+                }));
+            }
+            psram.write_slice(address, &[value])?;
+        }
+    }
+
+    // ⇕(r0)
+    for chunk in chunks(range, buf.len()) {
+        let buf = &mut buf[..chunk.len()];
+        psram.read_slice(chunk.start, buf)?;
+        if let Some(failure) = compare(chunk.start, buf, |_| 0x00) {
+            return Ok(Some(failure));
+        }
+    }
+    Ok(None)
+}
+
+/// Walking ones and walking zeros on the data lines, at a single `address`.
+pub fn data_bus<SPI: Transfer<u8>, CS: OutputPin>(
+    psram: &mut PSRAM<SPI, CS>,
+    address: u32,
+) -> TestResult<SPI, CS> {
+    for bit in 0..8 {
+        for &pattern in [1 << bit, !(1 << bit)].iter() {
+            psram.write_slice(address, &[pattern])?;
+            let mut actual = [0];
+            psram.read_slice(address, &mut actual)?;
+            if actual[0] != pattern {
+                return Ok(Some(Failure {
+                    address,
+                    expected: pattern,
+                    actual: actual[0],
+                }));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Checks that every address line within `range` selects a distinct byte.
+///
+/// Writes a pattern at each power of two offset from `range.start`, then
+/// writes the inverse at one offset at a time and checks that none of the
+/// others changed. A stuck or shorted address line makes two offsets alias.
+pub fn address_lines<SPI: Transfer<u8>, CS: OutputPin>(
+    psram: &mut PSRAM<SPI, CS>,
+    range: Range<u32>,
+) -> TestResult<SPI, CS> {
+    const PATTERN: u8 = 0xAA;
+    const ANTIPATTERN: u8 = 0x55;
+
+    if range.is_empty() {
+        return Ok(None);
+    }
+    let base = range.start;
+    let len = range.end - range.start;
+    // Offset zero first, then every power of two within the range
+    let offsets = || {
+        core::iter::once(0).chain(
+            (0..32)
+                .map(|bit| 1u32 << bit)
+                .take_while(move |&offset| offset < len),
+        )
+    };
+
+    for offset in offsets() {
+        psram.write_slice(base + offset, &[PATTERN])?;
+    }
+
+    for test in offsets() {
+        psram.write_slice(base + test, &[ANTIPATTERN])?;
+        for offset in offsets().filter(|&offset| offset != test) {
+            let mut actual = [0];
+            psram.read_slice(base + offset, &mut actual)?;
+            if actual[0] != PATTERN {
+                return Ok(Some(Failure {
+                    address: base + offset,
+                    expected: PATTERN,
+                    actual: actual[0],
+                }));
+            }
+        }
+        psram.write_slice(base + test, &[PATTERN])?;
+    }
+    Ok(None)
+}
+
+/// Alternating 0xAA and 0x55 over `range`, then the inverse.
+///
+/// `buf` is used as scratch space for each burst, see `march_c_minus`.
+/// Panics if `buf` is empty.
+pub fn checkerboard<SPI: Transfer<u8>, CS: OutputPin>(
+    psram: &mut PSRAM<SPI, CS>,
+    range: Range<u32>,
+    buf: &mut [u8],
+) -> TestResult<SPI, CS> {
+    for &even in [0xAA, 0x55].iter() {
+        let pattern = |address: u32| if address & 1 == 0 { even } else { !even };
+
+        for chunk in chunks(range.clone(), buf.len()) {
+            let buf = &mut buf[..chunk.len()];
+            for (address, byte) in chunk.clone().zip(buf.iter_mut()) {
+                *byte = pattern(address);
+            }
+            psram.write_slice(chunk.start, buf)?;
+        }
+
+        for chunk in chunks(range.clone(), buf.len()) {
+            let buf = &mut buf[..chunk.len()];
+            psram.read_slice(chunk.start, buf)?;
+            if let Some(failure) = compare(chunk.start, buf, pattern) {
+                return Ok(Some(failure));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Splits `range` into pieces of at most `len` bytes.
+fn chunks(range: Range<u32>, len: usize) -> impl Iterator<Item = Range<u32>> {
+    assert!(len > 0, "the scratch buffer must not be empty");
+    let step = u32::try_from(len).unwrap_or(u32::MAX);
+
+    (range.start..range.end)
+        .step_by(len)
+        .map(move |start| start..start.saturating_add(step).min(range.end))
+}
+
+/// Finds the first byte of `buf`, read from `start`, which does not match `expected`.
+fn compare(start: u32, buf: &[u8], expected: impl Fn(u32) -> u8) -> Option<Failure> {
+    (start..)
+        .zip(buf.iter())
+        .find(|&(address, &actual)| actual != expected(address))
+        .map(|(address, &actual)| Failure {
+            address,
+            expected: expected(address),
+            actual,
+        })
+}
@@ -0,0 +1,117 @@
+use crate::psram::{BurstLength, Freq, WRAP_SIZE};
+use core::convert::TryFrom;
+use core::ops::Range;
+
+/// Splits an access into transactions which the device accepts.
+///
+/// A transaction never crosses a wrap boundary (32 bytes in 32 byte burst mode,
+/// a page above 84MHz) and never carries more than `max_len` bytes, which keeps
+/// CS low for less than tCEM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Planner {
+    boundary: Option<u32>,
+    max_len: usize,
+}
+
+impl Planner {
+    /// Creates a planner for the given settings.
+    ///
+    /// Returns `None` if `page_size` is zero.
+    ///
+    /// # Parameters
+    ///
+    /// * **`freq`**: The frequency the device is running at. Above 84MHz bursts can't cross pages.
+    /// * **`burst_length`**: The wrap mode of the device.
+    /// * **`page_size`**: The page size of the device in bytes.
+    /// * **`max_len`**: The largest payload of a single transaction. Clamped to at least 1.
+    pub fn new(
+        freq: Freq,
+        burst_length: BurstLength,
+        page_size: u32,
+        max_len: usize,
+    ) -> Option<Self> {
+        if page_size == 0 {
+            return None;
+        }
+
+        let boundary = if burst_length == BurstLength::ThirtyTwoByte {
+            Some(WRAP_SIZE as u32)
+        } else if !freq.crosses_pages() {
+            Some(page_size)
+        } else {
+            None
+        };
+
+        Some(Planner {
+            boundary,
+            max_len: max_len.max(1),
+        })
+    }
+
+    /// The boundary transactions may not cross, if any.
+    pub fn boundary(&self) -> Option<u32> {
+        self.boundary
+    }
+
+    /// The largest payload of a single transaction.
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Splits `len` bytes starting at `address` into transactions.
+    ///
+    /// Bytes past the end of the 32 bit address space are left out.
+    pub fn plan(&self, address: u32, len: usize) -> Transactions {
+        Transactions {
+            planner: *self,
+            address,
+            offset: 0,
+            len,
+        }
+    }
+}
+
+/// A single command + address transaction produced by a `Planner`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    /// Device address of the first byte.
+    pub address: u32,
+    /// Part of the caller's buffer carried by this transaction.
+    pub range: Range<usize>,
+}
+
+/// Iterator over the transactions of one access.
+#[derive(Debug, Clone)]
+pub struct Transactions {
+    planner: Planner,
+    address: u32,
+    offset: usize,
+    len: usize,
+}
+
+impl Iterator for Transactions {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        if self.offset >= self.len {
+            return None;
+        }
+
+        let address = u32::try_from(self.offset)
+            .ok()
+            .and_then(|offset| self.address.checked_add(offset))?;
+        // Bytes left up to the end of the address space
+        let left = u64::from(u32::MAX - address) + 1;
+        let mut len = (self.len - self.offset)
+            .min(self.planner.max_len)
+            .min(usize::try_from(left).unwrap_or(usize::MAX));
+        if let Some(boundary) = self.planner.boundary {
+            let to_boundary = (boundary - address % boundary) as usize;
+            len = len.min(to_boundary);
+        }
+
+        let range = self.offset..self.offset + len;
+        self.offset += len;
+        Some(Transaction { address, range })
+    }
+}
@@ -1,3 +1,4 @@
+use crate::bus::{ChipSelect, QuadTransfer};
 use crate::Error;
 //pub mod prelude;
 
@@ -5,18 +6,53 @@ use hal::storage::{
     Address, AddressOffset, MultiRead, MultiWrite, SingleRead, SingleWrite, StorageSize,
 };
 
+use crate::device::{Device, ESP_PSRAM64};
+use crate::error::Invalid;
+use crate::plan::{Planner, Transaction, Transactions};
 use core::convert::TryInto;
+use core::ops::Range;
 //use core::fmt;
-use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::{Transfer, Write};
 use embedded_hal::digital::OutputPin;
+#[cfg(feature = "eh1")]
+use embedded_hal_1::spi::Operation;
+#[cfg(all(feature = "async", not(feature = "eh1")))]
+use embedded_hal_async::spi::Operation;
 
 /// Device identification and known good flag.
+///
+/// Decoded from the Read ID (0x9F) response: manufacturer ID, KGD byte and
+/// the 48 bit EID.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Identification {
+    /// Manufacturer ID. 0x0D for the ESP-PSRAM64(H).
+    pub manufacturer_id: u8,
+
+    /// Raw known good die byte.
+    pub kgd: u8,
+
     /// 48 Bit EID of the device
     pub eid: u64,
 
     /// True only after all tests are passed
     pub known_good_device: bool,
+
+    /// Density of the device, from EID[47:45].
+    pub density: Density,
+
+    /// Device generation, from EID[44:40].
+    pub generation: u8,
+
+    /// Remaining unique ID of the die, EID[39:0].
+    pub unique_id: u64,
+
+    /// Whether the device descriptor of the driver matches this ID.
+    ///
+    /// Set by `read_id`. False means no entry of `DEVICES` matched and the
+    /// previous descriptor was kept, so its capacity and limits may be wrong;
+    /// pass one for the part to `set_device`. Always false from `from_bytes`.
+    pub matched: bool,
 }
 
 #[allow(unused)]
@@ -25,51 +61,134 @@ enum KGD {
     Bad = 0b0101_0101,
 }
 
+/// Length of the Read ID response: MFID, KGD and six EID bytes.
+pub(crate) const ID_LEN: usize = 8;
+
 impl Identification {
     /// Build an Identification from Read ID bytes.
+    ///
+    /// `buf` starts with the MFID byte, i.e. the command and address phase are
+    /// already skipped.
     pub fn from_bytes<SPI: Transfer<u8>, CS: OutputPin>(
         buf: &[u8],
     ) -> Result<Self, Error<SPI, CS>> {
-        if buf.len() < 10 {
-            return Err(Error::InvalidDevice);
+        Ok(Self::parse(buf)?)
+    }
+
+    pub(crate) fn parse(buf: &[u8]) -> Result<Self, Invalid> {
+        if buf.len() < ID_LEN {
+            return Err(Invalid::Device);
         }
 
-        if buf[0] != 0x0D {
-            return Err(Error::InvalidDevice);
+        // Nothing answered on the bus
+        if buf[0] == 0x00 || buf[0] == 0xFF {
+            return Err(Invalid::Device);
         }
 
         let known_good = buf[1] == KGD::Good as u8;
 
         let mut bytes = [0; 8];
-
-        for (index, i) in buf[2..].iter().enumerate() {
-            bytes[index + 2] = *i;
-        }
+        bytes[2..].copy_from_slice(&buf[2..ID_LEN]);
 
         let eid = u64::from_be_bytes(bytes);
 
         Ok(Self {
-            eid: eid,
+            manufacturer_id: buf[0],
+            kgd: buf[1],
+            eid,
             known_good_device: known_good,
+            density: Density::from_code((eid >> 45) as u8 & 0b111),
+            generation: (eid >> 40) as u8 & 0b1_1111,
+            unique_id: eid & 0xFF_FFFF_FFFF,
+            matched: false,
         })
     }
 }
 
+/// Density of the device as reported in EID[47:45].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Density {
+    /// 16 Mbit (2MB)
+    Mbit16,
+    /// 32 Mbit (4MB)
+    Mbit32,
+    /// 64 Mbit (8MB)
+    Mbit64,
+    /// 128 Mbit (16MB)
+    Mbit128,
+    /// A density code this driver does not know.
+    Unknown(u8),
+}
+
+impl Density {
+    fn from_code(code: u8) -> Self {
+        match code {
+            0b000 => Density::Mbit16,
+            0b001 => Density::Mbit32,
+            0b010 => Density::Mbit64,
+            0b011 => Density::Mbit128,
+            code => Density::Unknown(code),
+        }
+    }
+
+    /// Size of the device in bytes, if known.
+    pub fn bytes(self) -> Option<u32> {
+        match self {
+            Density::Mbit16 => Some(2 * 1024 * 1024),
+            Density::Mbit32 => Some(4 * 1024 * 1024),
+            Density::Mbit64 => Some(8 * 1024 * 1024),
+            Density::Mbit128 => Some(16 * 1024 * 1024),
+            Density::Unknown(_) => None,
+        }
+    }
+}
+
+/// Device variants of the ESP-PSRAM64.
+///
+/// Both variants report the same ID, so the variant has to come from the
+/// board design. `ESP_PSRAM64` and `ESP_PSRAM64H` carry it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    /// ESP-PSRAM64, 1.8V, up to 144MHz.
+    EspPsram64,
+    /// ESP-PSRAM64H, 3.3V, up to 133MHz.
+    EspPsram64H,
+}
+
+impl Variant {
+    /// Supply voltage in millivolts.
+    pub fn millivolts(self) -> u16 {
+        match self {
+            Variant::EspPsram64 => 1800,
+            Variant::EspPsram64H => 3300,
+        }
+    }
+
+    /// Highest frequency the variant supports.
+    pub const fn max_freq(self) -> Freq {
+        match self {
+            Variant::EspPsram64 => Freq::OneFourFour,
+            Variant::EspPsram64H => Freq::OneThreeThree,
+        }
+    }
+}
+
 #[allow(unused)] // TODO support more features
-enum Opcode {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Opcode {
     /// Slow read at 33MHz
     Read = 0x03,
     /// Faster Read speed
     FastRead = 0x0B,
-    /// Really fast read using QuadSPI. Not supported yet.
+    /// Really fast read using QuadSPI.
     FastReadQuad = 0xEB,
     /// Slow write at 33MHz
     Write = 0x02,
-    /// Really fast write using QuadSPI. Not supported yet.
+    /// Really fast write using QuadSPI.
     QuadWrite = 0x38,
-    /// Enter QuadSPI Mode. Not supported yet.
+    /// Enter QuadSPI Mode.
     EnterQuadMode = 0x35,
-    /// Exit QuadSPI Mode. Not supported yet.
+    /// Exit QuadSPI Mode.
     ExitQuadMode = 0xF5,
     /// Enable the device to be reset
     ResetEnable = 0x66,
@@ -81,6 +200,71 @@ enum Opcode {
     ReadID = 0x9F,
 }
 
+impl Opcode {
+    /// Looks up a command byte.
+    #[cfg(any(feature = "sim", feature = "trace"))]
+    pub(crate) fn from_u8(byte: u8) -> Option<Self> {
+        let opcode = match byte {
+            0x03 => Opcode::Read,
+            0x0B => Opcode::FastRead,
+            0xEB => Opcode::FastReadQuad,
+            0x02 => Opcode::Write,
+            0x38 => Opcode::QuadWrite,
+            0x35 => Opcode::EnterQuadMode,
+            0xF5 => Opcode::ExitQuadMode,
+            0x66 => Opcode::ResetEnable,
+            0x99 => Opcode::Reset,
+            0xC0 => Opcode::SetBurstLength,
+            0x9F => Opcode::ReadID,
+            _ => return None,
+        };
+        Some(opcode)
+    }
+
+    /// Name of the command as used in the datasheet command table.
+    #[cfg(feature = "trace")]
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Opcode::Read => "READ",
+            Opcode::FastRead => "FAST_READ",
+            Opcode::FastReadQuad => "FAST_READ_QUAD",
+            Opcode::Write => "WRITE",
+            Opcode::QuadWrite => "QUAD_WRITE",
+            Opcode::EnterQuadMode => "ENTER_QUAD_MODE",
+            Opcode::ExitQuadMode => "EXIT_QUAD_MODE",
+            Opcode::ResetEnable => "RESET_ENABLE",
+            Opcode::Reset => "RESET",
+            Opcode::SetBurstLength => "WRAP_TOGGLE",
+            Opcode::ReadID => "READ_ID",
+        }
+    }
+
+    /// Whether a 24 bit address follows the command.
+    #[cfg(any(feature = "sim", feature = "trace"))]
+    pub(crate) fn has_address(self) -> bool {
+        matches!(
+            self,
+            Opcode::Read
+                | Opcode::FastRead
+                | Opcode::FastReadQuad
+                | Opcode::Write
+                | Opcode::QuadWrite
+                | Opcode::ReadID
+        )
+    }
+
+    /// Wait bytes between the address and the data, given the lanes of the command.
+    #[cfg(any(feature = "sim", feature = "trace"))]
+    pub(crate) fn wait_bytes(self, quad_command: bool) -> usize {
+        match self {
+            Opcode::FastRead if quad_command => 2,
+            Opcode::FastRead => FAST_READ_WAIT_BYTES,
+            Opcode::FastReadQuad => QUAD_READ_WAIT_BYTES,
+            _ => 0,
+        }
+    }
+}
+
 /// Frequency is used to enforce the page bountry limitations and burst length at runtime.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Freq {
@@ -96,6 +280,35 @@ pub enum Freq {
     OneFourFour,
 }
 
+impl Freq {
+    /// Picks the single lane read command for the frequency.
+    ///
+    /// Read (0x03) is only allowed up to 33MHz, above that Fast Read (0x0B)
+    /// with 8 wait cycles is required.
+    pub(crate) fn read_phases(self) -> Phases {
+        match self {
+            Freq::ThreeThree => Phases::single(Opcode::Read, 0),
+            _ => Phases::single(Opcode::FastRead, FAST_READ_WAIT_BYTES),
+        }
+    }
+
+    /// Whether bursts may cross page boundaries at this frequency.
+    pub fn crosses_pages(self) -> bool {
+        matches!(self, Freq::ThreeThree | Freq::EightyFour)
+    }
+
+    /// The clock frequency in MHz.
+    pub const fn mhz(self) -> u32 {
+        match self {
+            Freq::ThreeThree => 33,
+            Freq::EightyFour => 84,
+            Freq::OneZeroFour => 104,
+            Freq::OneThreeThree => 133,
+            Freq::OneFourFour => 144,
+        }
+    }
+}
+
 /// Burst Length is used to enforce the various operations at runtime.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BurstLength {
@@ -107,6 +320,378 @@ pub enum BurstLength {
     OneKByte,
 }
 
+/// Interface mode the device is currently in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Serial mode. Commands, addresses and data all use a single lane. Default after power up.
+    Spi,
+    /// Quad mode. Commands, addresses and data all use four lanes.
+    Qpi,
+}
+
+/// Read commands which can be selected per operation with `PSRAM::read_with`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadCommand {
+    /// Read (0x03). SPI mode only, up to 33MHz.
+    Read,
+    /// Fast Read (0x0B). Single lane in SPI mode, limited to 33MHz in QPI mode.
+    FastRead,
+    /// Fast Read Quad (0xEB). Address and data use four lanes in both modes.
+    FastReadQuad,
+}
+
+impl ReadCommand {
+    fn phases(self, mode: Mode, freq: Freq) -> Option<Phases> {
+        let quad_command = mode == Mode::Qpi;
+        let (opcode, quad_data, wait) = match (self, mode) {
+            (ReadCommand::Read, Mode::Spi) if freq == Freq::ThreeThree => (Opcode::Read, false, 0),
+            (ReadCommand::Read, _) => return None,
+            (ReadCommand::FastRead, Mode::Spi) => (Opcode::FastRead, false, FAST_READ_WAIT_BYTES),
+            // 4 wait cycles, but the device only manages 66MHz this way
+            (ReadCommand::FastRead, Mode::Qpi) if freq == Freq::ThreeThree => {
+                (Opcode::FastRead, true, 2)
+            }
+            (ReadCommand::FastRead, Mode::Qpi) => return None,
+            (ReadCommand::FastReadQuad, _) => (Opcode::FastReadQuad, true, QUAD_READ_WAIT_BYTES),
+        };
+
+        Some(Phases {
+            opcode,
+            quad_command,
+            quad_data,
+            wait,
+        })
+    }
+}
+
+/// Write commands which can be selected per operation with `PSRAM::write_with`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteCommand {
+    /// Write (0x02). Single lane in SPI mode.
+    Write,
+    /// Quad Write (0x38). Address and data use four lanes in both modes.
+    QuadWrite,
+}
+
+impl WriteCommand {
+    fn phases(self, mode: Mode) -> Phases {
+        let (opcode, quad_data) = match self {
+            WriteCommand::Write => (Opcode::Write, mode == Mode::Qpi),
+            WriteCommand::QuadWrite => (Opcode::QuadWrite, true),
+        };
+
+        Phases {
+            opcode,
+            quad_command: mode == Mode::Qpi,
+            quad_data,
+            wait: 0,
+        }
+    }
+}
+
+/// Lane usage of a single command.
+#[derive(Clone, Copy)]
+pub(crate) struct Phases {
+    pub(crate) opcode: Opcode,
+    /// Command byte is sent on four lanes.
+    pub(crate) quad_command: bool,
+    /// Address, wait and data phases use four lanes.
+    pub(crate) quad_data: bool,
+    /// Wait bytes between the address and the data, in the lane width of the data.
+    pub(crate) wait: usize,
+}
+
+impl Phases {
+    pub(crate) fn single(opcode: Opcode, wait: usize) -> Self {
+        Phases {
+            opcode,
+            quad_command: false,
+            quad_data: false,
+            wait,
+        }
+    }
+
+    /// Clocks needed to move one byte of address or data.
+    fn clocks_per_byte(&self) -> u32 {
+        if self.quad_data {
+            2
+        } else {
+            8
+        }
+    }
+
+    /// Clocks spent on the command, address and wait phases.
+    fn overhead_clocks(&self) -> u32 {
+        let command = if self.quad_command { 2 } else { 8 };
+        command + (3 + self.wait as u32) * self.clocks_per_byte()
+    }
+}
+
+pub(crate) enum Payload<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+impl<'a> Payload<'a> {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Payload::Read(buf) => buf.len(),
+            Payload::Write(buf) => buf.len(),
+        }
+    }
+
+    /// The part of the payload carried by one transaction.
+    pub(crate) fn slice(&mut self, range: Range<usize>) -> Payload<'_> {
+        match self {
+            Payload::Read(buf) => Payload::Read(&mut buf[range]),
+            Payload::Write(buf) => Payload::Write(&buf[range]),
+        }
+    }
+}
+
+/// The command, address and wait bytes sent before the data of a transaction.
+pub(crate) struct Header {
+    bytes: [u8; 4 + FAST_READ_WAIT_BYTES],
+    len: usize,
+}
+
+impl Header {
+    pub(crate) fn new(phases: Phases, address: u32) -> Self {
+        let mut bytes = [0; 4 + FAST_READ_WAIT_BYTES];
+        bytes[..4].copy_from_slice(&header(phases.opcode, address));
+        Header {
+            bytes,
+            len: 4 + phases.wait,
+        }
+    }
+
+    pub(crate) fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len]
+    }
+
+    /// The header and the payload as the operations of one `SpiDevice` transaction.
+    #[cfg(any(feature = "eh1", feature = "async"))]
+    pub(crate) fn operations<'a>(&'a self, payload: Payload<'a>) -> [Operation<'a, u8>; 2] {
+        let header = Operation::Write(&self.bytes[..self.len]);
+        match payload {
+            Payload::Read(buf) => [header, Operation::Read(buf)],
+            Payload::Write(buf) => [header, Operation::Write(buf)],
+        }
+    }
+}
+
+/// Number of wait cycles for Fast Read in SPI mode, 8 clocks on a single lane.
+pub(crate) const FAST_READ_WAIT_BYTES: usize = 1;
+
+/// Number of wait cycles for Fast Read Quad. Each byte is two clocks on the quad lanes.
+pub(crate) const QUAD_READ_WAIT_BYTES: usize = 3;
+
+/// Maximum time CS may stay low (tCEM) for standard temperature parts, in nanoseconds.
+///
+/// The device only refreshes itself while CS is high.
+pub const TCEM_NS: u32 = 8_000;
+
+/// Time the device needs after power up before the first command.
+pub(crate) const POWER_UP_US: u32 = 150;
+
+/// Time the device needs to come out of reset. tRST is only 50ns, so this is
+/// the shortest delay that can be requested.
+pub(crate) const RESET_US: u32 = 1;
+
+/// Line size of a wrapped burst in bytes.
+pub const WRAP_SIZE: usize = 32;
+
+/// Driver state shared by the drivers of this crate.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Settings {
+    pub(crate) freq: Freq,
+    /// The clock the bus actually runs at in MHz, at most `freq`.
+    pub(crate) clock_mhz: u32,
+    pub(crate) burst_length: BurstLength,
+    pub(crate) mode: Mode,
+    pub(crate) tcem: u32,
+    pub(crate) device: Device,
+}
+
+impl Settings {
+    pub(crate) fn new(freq: Freq, burst_length: BurstLength) -> Self {
+        Settings {
+            freq,
+            clock_mhz: freq.mhz(),
+            burst_length,
+            mode: Mode::Spi,
+            tcem: ESP_PSRAM64.tcem_ns,
+            device: ESP_PSRAM64,
+        }
+    }
+
+    /// Checks the combination given to the init functions.
+    pub(crate) fn check_init(freq: Freq, burst_length: BurstLength) -> Result<(), Invalid> {
+        // Only the slow read can stream without a length limit
+        if burst_length == BurstLength::None && freq != Freq::ThreeThree {
+            return Err(Invalid::Mode);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_freq(&mut self, freq: Freq) -> Result<(), Invalid> {
+        Self::check_init(freq, self.burst_length)?;
+        Self::check_freq(freq, &self.device)?;
+
+        self.freq = freq;
+        self.clock_mhz = freq.mhz();
+        Ok(())
+    }
+
+    pub(crate) fn set_clock_mhz(&mut self, mhz: u32) {
+        self.clock_mhz = mhz.clamp(1, self.freq.mhz());
+    }
+
+    /// Checks that `device` can run at `freq`. Above 33MHz the single lane
+    /// reads need Fast Read.
+    fn check_freq(freq: Freq, device: &Device) -> Result<(), Invalid> {
+        if freq.mhz() > device.max_mhz || !device.supports(freq.read_phases().opcode) {
+            return Err(Invalid::Mode);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_device(&mut self, device: Device) -> Result<(), Invalid> {
+        if device.page_size == 0 {
+            return Err(Invalid::Device);
+        }
+        Self::check_freq(self.freq, &device)?;
+
+        self.tcem = device.tcem_ns;
+        self.device = device;
+        Ok(())
+    }
+
+    /// Decodes a Read ID response and picks the device descriptor for it.
+    pub(crate) fn identify(&mut self, buf: &[u8]) -> Result<Identification, Invalid> {
+        let mut id = Identification::parse(buf)?;
+        self.select_device(&id)?;
+        id.matched = self.device.matches(&id);
+        Ok(id)
+    }
+
+    /// Keeps the current descriptor if it matches the ID, otherwise looks one
+    /// up. Parts missing from `DEVICES` keep the current descriptor.
+    pub(crate) fn select_device(&mut self, id: &Identification) -> Result<(), Invalid> {
+        if !self.device.matches(id) {
+            if let Some(device) = Device::lookup(id) {
+                self.set_device(*device)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the ID read during the power up sequence: the die has to be
+    /// known good and match the descriptor.
+    pub(crate) fn check_id(&self, id: &Identification) -> Result<(), Invalid> {
+        if !id.known_good_device || !self.device.matches(id) {
+            return Err(Invalid::Device);
+        }
+        Ok(())
+    }
+
+    /// The planner for single lane reads.
+    pub(crate) fn planner(&self) -> Planner {
+        self.planner_for(self.freq.read_phases())
+    }
+
+    pub(crate) fn planner_for(&self, phases: Phases) -> Planner {
+        Planner::new(
+            self.freq,
+            self.burst_length,
+            self.device.page_size,
+            self.max_burst(phases),
+        )
+        .expect("set_device rejects a zero page size")
+    }
+
+    /// Splits an access into transactions, after checking that it lies within the device.
+    pub(crate) fn plan(
+        &self,
+        phases: Phases,
+        address: u32,
+        len: usize,
+    ) -> Result<Transactions, Invalid> {
+        self.check_bounds(address, len)?;
+        Ok(self.planner_for(phases).plan(address, len))
+    }
+
+    /// The largest payload that fits into one CS window for the given command.
+    ///
+    /// Uses the actual bus clock, as a slower clock keeps CS low for longer.
+    /// Always at least one byte, so that a too short tCEM still makes progress.
+    pub(crate) fn max_burst(&self, phases: Phases) -> usize {
+        let clocks = u64::from(self.tcem) * u64::from(self.clock_mhz) / 1000;
+        let payload = clocks.saturating_sub(u64::from(phases.overhead_clocks()))
+            / u64::from(phases.clocks_per_byte());
+        payload.max(1).try_into().unwrap_or(usize::MAX)
+    }
+
+    /// Checks a burst length change. Returns whether the wrap boundary toggle
+    /// has to be sent to the device.
+    pub(crate) fn burst_toggle(&self, burst: BurstLength) -> Result<bool, Invalid> {
+        if burst == BurstLength::None && self.freq != Freq::ThreeThree {
+            return Err(Invalid::Mode);
+        }
+
+        if burst == BurstLength::ThirtyTwoByte && !self.device.commands.wrap {
+            return Err(Invalid::Mode);
+        }
+
+        //Send the burst command if the new state is 32 and the old state is not 32
+        // or the new state is not 32 and the old state is 32
+        Ok((burst == BurstLength::ThirtyTwoByte)
+            != (self.burst_length == BurstLength::ThirtyTwoByte))
+    }
+
+    /// Checks that `len` bytes starting at `address` lie within the device.
+    pub(crate) fn check_bounds(&self, address: u32, len: usize) -> Result<(), Invalid> {
+        let end = u64::from(address) + len as u64;
+        if end > u64::from(self.device.capacity) {
+            return Err(Invalid::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Updates the state to match a device that was just reset.
+    pub(crate) fn reset_done(&mut self) {
+        self.mode = Mode::Spi;
+        if self.burst_length == BurstLength::ThirtyTwoByte {
+            self.burst_length = BurstLength::OneKByte;
+        }
+    }
+
+    /// Splits a wrapped access of a whole line into transactions.
+    ///
+    /// The device wraps within the line, so a burst which does not fit into
+    /// tCEM is continued with a new command at the wrapped address.
+    pub(crate) fn wrapped(
+        &self,
+        phases: Phases,
+        address: u32,
+    ) -> Result<impl Iterator<Item = Transaction>, Invalid> {
+        if self.burst_length != BurstLength::ThirtyTwoByte {
+            return Err(Invalid::Mode);
+        }
+
+        let line = address - address % WRAP_SIZE as u32;
+        self.check_bounds(line, WRAP_SIZE)?;
+        let max = self.max_burst(phases);
+        Ok((0..WRAP_SIZE).step_by(max).map(move |offset| {
+            let len = max.min(WRAP_SIZE - offset);
+            Transaction {
+                address: line + (address + offset as u32) % WRAP_SIZE as u32,
+                range: offset..offset + len,
+            }
+        }))
+    }
+}
+
 /// Driver for ESP SPI Psuedo SRAM chips.
 ///
 /// # Type Parameters
@@ -120,8 +705,7 @@ pub enum BurstLength {
 pub struct PSRAM<SPI: Transfer<u8>, CS: OutputPin> {
     spi: SPI,
     cs: CS,
-    freq: Freq,
-    burst_length: BurstLength,
+    settings: Settings,
 }
 
 impl<SPI: Transfer<u8>, CS: OutputPin> PSRAM<SPI, CS> {
@@ -134,6 +718,7 @@ impl<SPI: Transfer<u8>, CS: OutputPin> PSRAM<SPI, CS> {
     /// * **`cs`**: The **C**hip-**S**elect Pin connected to the `\CS`/`\CE` pin
     ///   of the flash chip. Will be driven low when accessing the device.
     /// * **`freq`**: The maximum frequency that the deivce is running at. Important for cross page access
+    ///   and selects Read (0x03) at 33MHz or Fast Read (0x0B) above it.
     /// * **`burst_length`**: The maximum payload size.
     pub fn init(
         spi: SPI,
@@ -141,88 +726,621 @@ impl<SPI: Transfer<u8>, CS: OutputPin> PSRAM<SPI, CS> {
         freq: Freq,
         burst_length: BurstLength,
     ) -> Result<Self, Error<SPI, CS>> {
-        let mut this = Self {
+        Settings::check_init(freq, burst_length)?;
+        let mut this = Self::new(spi, cs, freq, burst_length);
+
+        //Set the burst_length now
+        if burst_length == BurstLength::ThirtyTwoByte {
+            //Send the command to the device
+            this.command(&mut [Opcode::SetBurstLength as u8])?;
+        }
+
+        Ok(this)
+    }
+
+    /// Creates a new PSRAM driver, running the power up sequence from the datasheet.
+    ///
+    /// Waits 150µs for the device to power up, resets it, checks that the ID
+    /// belongs to a known good device the driver has a descriptor for and
+    /// then sets the burst length. The driver returned is ready to use.
+    ///
+    /// # Parameters
+    ///
+    /// * **`spi`**, **`cs`**, **`freq`**, **`burst_length`**: As for `init`.
+    /// * **`delay`**: Used to wait for the power up and the reset.
+    pub fn init_with_delay<D: DelayUs<u32>>(
+        spi: SPI,
+        cs: CS,
+        freq: Freq,
+        burst_length: BurstLength,
+        delay: &mut D,
+    ) -> Result<Self, Error<SPI, CS>> {
+        Settings::check_init(freq, burst_length)?;
+        // The device starts without 32 byte wrapping, set_burst will change that if requested
+        let mut this = Self::new(spi, cs, freq, BurstLength::OneKByte);
+
+        delay.try_delay_us(POWER_UP_US).map_err(|_| Error::Delay)?;
+        this.reset(delay)?;
+
+        let id = this.read_id()?;
+        this.settings.check_id(&id)?;
+
+        this.set_burst(burst_length)?;
+        Ok(this)
+    }
+
+    fn new(spi: SPI, cs: CS, freq: Freq, burst_length: BurstLength) -> Self {
+        Self {
             spi,
             cs,
-            freq,
-            burst_length,
-        };
+            settings: Settings::new(freq, burst_length),
+        }
+    }
+
+    /// The SPI master, for changing its configuration.
+    pub(crate) fn spi_mut(&mut self) -> &mut SPI {
+        &mut self.spi
+    }
+
+    /// The interface mode the device is in.
+    pub fn mode(&self) -> Mode {
+        self.settings.mode
+    }
+
+    /// The descriptor of the attached device.
+    pub fn device(&self) -> &Device {
+        &self.settings.device
+    }
+
+    /// Uses the given descriptor for the attached device. Defaults to `ESP_PSRAM64`.
+    ///
+    /// `read_id` keeps a descriptor that matches the ID, so this is also the
+    /// way to use parts missing from `DEVICES`. Resets the tCEM limit to the
+    /// one of the device. Returns `Error::InvalidMode` if the device can't run
+    /// at the configured `Freq`, either because it is too slow or because it
+    /// lacks Fast Read above 33MHz, and `Error::InvalidDevice` if its page
+    /// size is zero.
+    pub fn set_device(&mut self, device: Device) -> Result<(), Error<SPI, CS>> {
+        Ok(self.settings.set_device(device)?)
+    }
+
+    /// The frequency the bus is assumed to run at.
+    pub fn freq(&self) -> Freq {
+        self.settings.freq
+    }
+
+    /// Changes the frequency the bus is assumed to run at.
+    ///
+    /// Only updates the driver, the SPI master has to be switched to the same
+    /// clock. Returns `Error::InvalidMode` if the device or the burst length
+    /// does not allow the frequency. Also resets `clock_mhz` to `freq`.
+    pub fn set_freq(&mut self, freq: Freq) -> Result<(), Error<SPI, CS>> {
+        Ok(self.settings.set_freq(freq)?)
+    }
+
+    /// Sets the clock the SPI master actually produces, in MHz rounded down.
+    ///
+    /// Masters often can't hit `freq` exactly and pick a slower clock. The
+    /// commands and page crossing still follow `freq`, but transactions are
+    /// sized for tCEM at this clock. Clamped to `1..=freq.mhz()`.
+    pub fn set_clock_mhz(&mut self, mhz: u32) {
+        self.settings.set_clock_mhz(mhz);
+    }
+
+    /// The clock the bus is assumed to actually run at, in MHz.
+    pub fn clock_mhz(&self) -> u32 {
+        self.settings.clock_mhz
+    }
+
+    /// Sets the maximum time CS may stay low, in nanoseconds. Defaults to the tCEM of the device.
+    ///
+    /// Reads and writes are split into several transactions so that no single
+    /// one exceeds this at `clock_mhz`. Extended temperature parts
+    /// need a shorter limit, check their datasheet.
+    pub fn set_tcem(&mut self, nanoseconds: u32) {
+        self.settings.tcem = nanoseconds;
+    }
+
+    /// The maximum time CS may stay low, in nanoseconds.
+    pub fn tcem(&self) -> u32 {
+        self.settings.tcem
+    }
 
-        if freq != Freq::ThreeThree {
+    /// The planner used for single lane reads.
+    ///
+    /// Reads have the largest overhead, so its transactions are legal for every command.
+    pub fn planner(&self) -> Planner {
+        self.settings.planner()
+    }
+
+    /// The capacity of the device in bytes.
+    pub fn capacity(&self) -> u32 {
+        self.settings.device.capacity
+    }
+
+    /// Single lane read used by the storage traits and the memory tests.
+    pub(crate) fn read_slice(
+        &mut self,
+        address: u32,
+        buf: &mut [u8],
+    ) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode != Mode::Spi {
             return Err(Error::InvalidMode);
         }
+        let phases = self.settings.freq.read_phases();
+        for transaction in self.settings.plan(phases, address, buf.len())? {
+            self.single_transaction(
+                phases,
+                transaction.address,
+                Payload::Read(&mut buf[transaction.range]),
+            )?;
+        }
+        Ok(())
+    }
 
-        //Set the burst_length now
-        if burst_length == BurstLength::ThirtyTwoByte {
-            //Send the command to the device
-            let mut cmd_buf = [Opcode::SetBurstLength as u8];
-            this.cs.try_set_low().map_err(Error::Gpio)?;
-            let spi_result = this.spi.try_transfer(&mut cmd_buf);
-            spi_result.map(|_| ()).map_err(Error::Spi)?;
-            this.cs.try_set_high().map_err(Error::Gpio)?;
+    /// Single lane write used by the storage traits and the memory tests.
+    pub(crate) fn write_slice(&mut self, address: u32, buf: &[u8]) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode != Mode::Spi {
+            return Err(Error::InvalidMode);
+        }
+        let phases = Phases::single(Opcode::Write, 0);
+        for transaction in self.settings.plan(phases, address, buf.len())? {
+            self.single_transaction(
+                phases,
+                transaction.address,
+                Payload::Write(&buf[transaction.range]),
+            )?;
         }
+        Ok(())
+    }
 
-        Ok(this)
+    /// Runs the first transaction of a single lane access and returns the
+    /// number of bytes it moved. Used by the resumable transfers.
+    pub(crate) fn step(
+        &mut self,
+        address: u32,
+        mut payload: Payload<'_>,
+    ) -> Result<usize, Error<SPI, CS>> {
+        if self.settings.mode != Mode::Spi {
+            return Err(Error::InvalidMode);
+        }
+
+        let phases = match payload {
+            Payload::Read(_) => self.settings.freq.read_phases(),
+            Payload::Write(_) => Phases::single(Opcode::Write, 0),
+        };
+        let transaction = match self.settings.plan(phases, address, payload.len())?.next() {
+            Some(transaction) => transaction,
+            None => return Ok(0),
+        };
+        let moved = transaction.range.len();
+        self.single_transaction(
+            phases,
+            transaction.address,
+            payload.slice(transaction.range),
+        )?;
+        Ok(moved)
+    }
+
+    /// Runs one single lane command, address, wait and data sequence.
+    fn single_transaction(
+        &mut self,
+        phases: Phases,
+        address: u32,
+        payload: Payload<'_>,
+    ) -> Result<(), Error<SPI, CS>> {
+        self.selected(|spi| Self::run_single(spi, phases, address, payload))
+    }
+
+    fn run_single(
+        spi: &mut SPI,
+        phases: Phases,
+        address: u32,
+        payload: Payload<'_>,
+    ) -> Result<(), SPI::Error> {
+        spi.try_transfer(Header::new(phases, address).bytes_mut())?;
+
+        match payload {
+            Payload::Read(buf) => spi.try_transfer(buf).map(|_| ()),
+            Payload::Write(buf) => {
+                // Transfer overwrites its buffer, so stage the data on the stack
+                let mut staging = [0; 32];
+                for chunk in buf.chunks(staging.len()) {
+                    let staging = &mut staging[..chunk.len()];
+                    staging.copy_from_slice(chunk);
+                    spi.try_transfer(staging)?;
+                }
+                Ok(())
+            }
+        }
     }
 
     fn command(&mut self, bytes: &mut [u8]) -> Result<(), Error<SPI, CS>> {
-        // If the SPI transfer fails, make sure to disable CS anyways
-        self.cs.try_set_low().map_err(Error::Gpio)?;
-        let spi_result = self.spi.try_transfer(bytes).map_err(Error::Spi);
-        self.cs.try_set_high().map_err(Error::Gpio)?;
-        spi_result?;
-        Ok(())
+        self.selected(|spi| spi.try_transfer(bytes).map(|_| ()))
+    }
+
+    /// Runs `f` with CS low.
+    ///
+    /// CS is released again even if `f` fails. An SPI error is only returned
+    /// if CS could be released.
+    fn selected<R>(
+        &mut self,
+        f: impl FnOnce(&mut SPI) -> Result<R, SPI::Error>,
+    ) -> Result<R, Error<SPI, CS>> {
+        let cs = ChipSelect::select(&mut self.cs).map_err(Error::Gpio)?;
+        let spi_result = f(&mut self.spi);
+        cs.release().map_err(Error::Gpio)?;
+        spi_result.map_err(Error::Spi)
     }
 
     /// Reads the manufacturer/device identification.
+    ///
+    /// Also picks the device descriptor: the current one is kept if it matches
+    /// the ID, otherwise the first match from `DEVICES` is used. If nothing
+    /// matches, the current descriptor is kept as well and
+    /// `Identification::matched` is false, so the ID of an unknown part can be
+    /// used to pick one for `set_device`. Only available in SPI mode.
     pub fn read_id(&mut self) -> Result<Identification, Error<SPI, CS>> {
-        // Optimistically read 12 bytes, even though some identifiers will be shorter
-        let mut buf: [u8; 14] = [0; 14];
+        if self.settings.mode != Mode::Spi {
+            return Err(Error::InvalidMode);
+        }
+
+        let mut buf = [0; 4 + ID_LEN];
         buf[0] = Opcode::ReadID as u8;
         self.command(&mut buf)?;
 
-        // Skip buf[0..3] (SPI read response byte)
-        Identification::from_bytes(&buf[4..])
+        // Skip buf[0..3] (command and address phase)
+        Ok(self.settings.identify(&buf[4..])?)
+    }
+
+    /// Resets the device and waits until it is ready again.
+    ///
+    /// Afterwards the device is in SPI mode without 32 byte wrapping, and the
+    /// driver state is changed to match. Only available in SPI mode.
+    pub fn reset<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode != Mode::Spi {
+            return Err(Error::InvalidMode);
+        }
+
+        self.send_reset()?;
+        self.reset_done(delay)
+    }
+
+    /// Brings the device back into a known state after a bus error.
+    ///
+    /// Resets the device and then restores the burst length the driver had
+    /// before. Only available in SPI mode.
+    pub fn recover<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Result<(), Error<SPI, CS>> {
+        let burst = self.settings.burst_length;
+        self.reset(delay)?;
+        self.set_burst(burst)
+    }
+
+    /// Waits for the reset to finish and updates the driver to the reset state.
+    fn reset_done<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Result<(), Error<SPI, CS>> {
+        delay.try_delay_us(RESET_US).map_err(|_| Error::Delay)?;
+        self.settings.reset_done();
+        Ok(())
     }
 
     /// Reset the Device
-    fn reset(&mut self) -> Result<(), Error<SPI, CS>> {
+    fn send_reset(&mut self) -> Result<(), Error<SPI, CS>> {
         //Enable the Reset
-        let mut cmd_buf = [Opcode::ResetEnable as u8];
-        self.cs.try_set_low().map_err(Error::Gpio)?;
-        let spi_result = self.spi.try_transfer(&mut cmd_buf);
-        spi_result.map(|_| ()).map_err(Error::Spi)?;
-        self.cs.try_set_high().map_err(Error::Gpio)?;
+        self.command(&mut [Opcode::ResetEnable as u8])?;
 
         //Trigger the reset
-        let mut cmd_buf = [Opcode::Reset as u8];
-        self.cs.try_set_low().map_err(Error::Gpio)?;
-        let spi_result = self.spi.try_transfer(&mut cmd_buf);
-        self.cs.try_set_high().map_err(Error::Gpio)?;
-        spi_result.map(|_| ()).map_err(Error::Spi)
+        self.command(&mut [Opcode::Reset as u8])
     }
 
-    fn set_burst(&mut self, burst: BurstLength) -> Result<(), Error<SPI, CS>> {
-        //Send the burst command if the new state is 32 and the old state is not 32
-        // or the new state is not 32 and the old state is 32
-        if (burst == BurstLength::ThirtyTwoByte && self.burst_length != BurstLength::ThirtyTwoByte)
-            || (burst != BurstLength::ThirtyTwoByte
-                && self.burst_length == BurstLength::ThirtyTwoByte)
-        {
+    /// Changes the burst length, toggling the wrap boundary of the device if needed.
+    ///
+    /// `BurstLength::ThirtyTwoByte` is needed for `read_wrapped` and `write_wrapped`.
+    /// Only available in SPI mode, see `set_burst_quad` for QPI mode.
+    ///
+    /// On a bus error the driver keeps the old burst length, but the toggle may
+    /// still have reached the device. Use `recover` to bring both in line.
+    pub fn set_burst(&mut self, burst: BurstLength) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode != Mode::Spi {
+            return Err(Error::InvalidMode);
+        }
+
+        if self.settings.burst_toggle(burst)? {
             //Send the command to the device
-            let mut cmd_buf = [Opcode::SetBurstLength as u8];
-            self.cs.try_set_low().map_err(Error::Gpio)?;
-            let spi_result = self.spi.try_transfer(&mut cmd_buf);
-            spi_result.map(|_| ()).map_err(Error::Spi)?;
-            self.cs.try_set_high().map_err(Error::Gpio)?;
+            self.command(&mut [Opcode::SetBurstLength as u8])?;
+        }
+
+        self.settings.burst_length = burst;
+
+        Ok(())
+    }
+
+    /// The current burst length.
+    pub fn burst_length(&self) -> BurstLength {
+        self.settings.burst_length
+    }
+
+    /// Reads the whole 32 byte line containing `address`, starting at `address`.
+    ///
+    /// `buf[0]` holds the byte at `address` (the critical word), the following
+    /// bytes wrap around within the line. Needs `BurstLength::ThirtyTwoByte`.
+    pub fn read_wrapped(
+        &mut self,
+        address: Address<u32>,
+        buf: &mut [u8; WRAP_SIZE],
+    ) -> Result<(), Error<SPI, CS>> {
+        let phases = self.settings.freq.read_phases();
+        self.wrapped(phases, address.0, Payload::Read(buf))
+    }
+
+    /// Writes the whole 32 byte line containing `address`, starting at `address`.
+    ///
+    /// `buf[0]` is written to `address`, the following bytes wrap around within
+    /// the line. Needs `BurstLength::ThirtyTwoByte`.
+    pub fn write_wrapped(
+        &mut self,
+        address: Address<u32>,
+        buf: &[u8; WRAP_SIZE],
+    ) -> Result<(), Error<SPI, CS>> {
+        let phases = Phases::single(Opcode::Write, 0);
+        self.wrapped(phases, address.0, Payload::Write(buf))
+    }
+
+    fn wrapped(
+        &mut self,
+        phases: Phases,
+        address: u32,
+        mut payload: Payload<'_>,
+    ) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode != Mode::Spi {
+            return Err(Error::InvalidMode);
+        }
+
+        for transaction in self.settings.wrapped(phases, address)? {
+            let chunk = payload.slice(transaction.range);
+            self.single_transaction(phases, transaction.address, chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl<SPI: QuadTransfer<u8>, CS: OutputPin> PSRAM<SPI, CS> {
+    /// Switches the device into QPI mode.
+    ///
+    /// While in QPI mode the single lane storage traits, `read_id` and the
+    /// wrapped accesses return `Error::InvalidMode`; use `read_quad`/`write_quad`
+    /// or `read_with`/`write_with` instead, and `set_burst_quad` for the burst length.
+    pub fn enter_quad_mode(&mut self) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode == Mode::Qpi {
+            return Ok(());
+        }
+
+        if !self.settings.device.commands.quad {
+            return Err(Error::InvalidMode);
+        }
+
+        self.command(&mut [Opcode::EnterQuadMode as u8])?;
+        self.settings.mode = Mode::Qpi;
+        Ok(())
+    }
+
+    /// Resets the device from QPI mode and waits until it is ready again.
+    ///
+    /// Afterwards the device is in SPI mode without 32 byte wrapping, and the
+    /// driver state is changed to match. Only available in QPI mode.
+    pub fn reset_quad<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode != Mode::Qpi {
+            return Err(Error::InvalidMode);
+        }
+
+        self.send_quad_reset()?;
+        self.reset_done(delay)
+    }
+
+    /// Brings the device back into a known state after a bus error.
+    ///
+    /// Sends the reset in both QPI and SPI encoding, as the mode of the device
+    /// is not known after an error, then restores the burst length the driver
+    /// had before. Works in both modes.
+    ///
+    /// The QPI encoded reset goes first. A device in QPI mode accepts both; one
+    /// already in SPI mode ignores the QPI encoded one, so a bus analyser or
+    /// `sim::Violation::WrongMode` shows it as sent in the wrong mode.
+    pub fn recover_quad<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Result<(), Error<SPI, CS>> {
+        let burst = self.settings.burst_length;
+        self.send_quad_reset()?;
+        self.send_reset()?;
+        self.reset_done(delay)?;
+        self.set_burst(burst)
+    }
+
+    fn send_quad_reset(&mut self) -> Result<(), Error<SPI, CS>> {
+        for opcode in [Opcode::ResetEnable, Opcode::Reset].iter() {
+            self.selected(|spi| spi.try_quad_write(&[*opcode as u8]))?;
+        }
+        Ok(())
+    }
+
+    /// Changes the burst length like `set_burst`, but also in QPI mode.
+    ///
+    /// In QPI mode the wrap boundary toggle is sent over the four lanes.
+    pub fn set_burst_quad(&mut self, burst: BurstLength) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode == Mode::Spi {
+            return self.set_burst(burst);
+        }
+
+        if self.settings.burst_toggle(burst)? {
+            self.selected(|spi| spi.try_quad_write(&[Opcode::SetBurstLength as u8]))?;
+        }
+
+        self.settings.burst_length = burst;
+        Ok(())
+    }
+
+    /// Switches the device back into SPI mode.
+    pub fn exit_quad_mode(&mut self) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode == Mode::Spi {
+            return Ok(());
+        }
+
+        self.selected(|spi| spi.try_quad_write(&[Opcode::ExitQuadMode as u8]))?;
+        self.settings.mode = Mode::Spi;
+        Ok(())
+    }
+
+    /// Reads `buf.len()` bytes starting at `address` with Fast Read Quad.
+    ///
+    /// Works in both modes. In SPI mode only the command uses a single lane.
+    pub fn read_quad(
+        &mut self,
+        address: Address<u32>,
+        buf: &mut [u8],
+    ) -> Result<(), Error<SPI, CS>> {
+        self.read_with(ReadCommand::FastReadQuad, address, buf)
+    }
+
+    /// Writes `buf` starting at `address` with Quad Write.
+    ///
+    /// Works in both modes. In SPI mode only the command uses a single lane.
+    pub fn write_quad(&mut self, address: Address<u32>, buf: &[u8]) -> Result<(), Error<SPI, CS>> {
+        self.write_with(WriteCommand::QuadWrite, address, buf)
+    }
+
+    /// Reads `buf.len()` bytes starting at `address` using the given command.
+    ///
+    /// Returns `Error::InvalidMode` if the command is not available in the
+    /// current mode or at the configured frequency, and `Error::OutOfBounds`
+    /// if the access reaches past the end of the device.
+    pub fn read_with(
+        &mut self,
+        command: ReadCommand,
+        address: Address<u32>,
+        buf: &mut [u8],
+    ) -> Result<(), Error<SPI, CS>> {
+        let phases = command
+            .phases(self.settings.mode, self.settings.freq)
+            .filter(|phases| self.settings.device.supports(phases.opcode))
+            .ok_or(Error::InvalidMode)?;
+        for transaction in self.settings.plan(phases, address.0, buf.len())? {
+            self.transaction(
+                phases,
+                transaction.address,
+                Payload::Read(&mut buf[transaction.range]),
+            )?;
         }
+        Ok(())
+    }
 
-        self.burst_length = burst;
+    /// Writes `buf` starting at `address` using the given command.
+    ///
+    /// Returns `Error::InvalidMode` if the command is not available in the
+    /// current mode, and `Error::OutOfBounds` if the access reaches past the
+    /// end of the device.
+    pub fn write_with(
+        &mut self,
+        command: WriteCommand,
+        address: Address<u32>,
+        buf: &[u8],
+    ) -> Result<(), Error<SPI, CS>> {
+        let phases = command.phases(self.settings.mode);
+        if !self.settings.device.supports(phases.opcode) {
+            return Err(Error::InvalidMode);
+        }
+        for transaction in self.settings.plan(phases, address.0, buf.len())? {
+            self.transaction(
+                phases,
+                transaction.address,
+                Payload::Write(&buf[transaction.range]),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn transaction(
+        &mut self,
+        phases: Phases,
+        address: u32,
+        payload: Payload<'_>,
+    ) -> Result<(), Error<SPI, CS>> {
+        self.selected(|spi| Self::run_phases(spi, phases, address, payload))
+    }
+
+    fn run_phases(
+        spi: &mut SPI,
+        phases: Phases,
+        address: u32,
+        payload: Payload<'_>,
+    ) -> Result<(), SPI::Error> {
+        if !phases.quad_data {
+            return Self::run_single(spi, phases, address, payload);
+        }
+
+        let mut cmd_buf = header(phases.opcode, address);
+        let mut wait = [0; QUAD_READ_WAIT_BYTES];
+
+        if phases.quad_command {
+            spi.try_quad_write(&cmd_buf[..1])?;
+        } else {
+            spi.try_transfer(&mut cmd_buf[..1])?;
+        }
+        spi.try_quad_write(&cmd_buf[1..])?;
+        spi.try_quad_read(&mut wait[..phases.wait])?;
+
+        match payload {
+            Payload::Read(buf) => spi.try_quad_read(buf),
+            Payload::Write(buf) => spi.try_quad_write(buf),
+        }
+    }
+}
 
+impl<SPI, CS> PSRAM<SPI, CS>
+where
+    SPI: Transfer<u8> + Write<u8, Error = <SPI as Transfer<u8>>::Error>,
+    CS: OutputPin,
+{
+    /// Writes `buf` starting at `address` without touching the source buffer.
+    ///
+    /// Uses the write only SPI trait, so unlike `try_write_slice` the data can
+    /// come from a `&'static [u8]` or a shared buffer and is not copied first.
+    /// Only available in SPI mode.
+    pub fn write_from(&mut self, address: Address<u32>, buf: &[u8]) -> Result<(), Error<SPI, CS>> {
+        if self.settings.mode != Mode::Spi {
+            return Err(Error::InvalidMode);
+        }
+
+        let phases = Phases::single(Opcode::Write, 0);
+        for transaction in self.settings.plan(phases, address.0, buf.len())? {
+            let cmd_buf = header(phases.opcode, transaction.address);
+            self.selected(|spi| {
+                spi.try_write(&cmd_buf)?;
+                spi.try_write(&buf[transaction.range])
+            })?;
+        }
         Ok(())
     }
 }
 
+impl Device {
+    /// Whether the device supports the opcode.
+    fn supports(&self, opcode: Opcode) -> bool {
+        match opcode {
+            Opcode::FastRead => self.commands.fast_read,
+            Opcode::FastReadQuad | Opcode::QuadWrite | Opcode::EnterQuadMode => self.commands.quad,
+            Opcode::SetBurstLength => self.commands.wrap,
+            _ => true,
+        }
+    }
+}
+
+/// Builds the command and 24 bit address phase of a memory access.
+pub(crate) fn header(opcode: Opcode, address: u32) -> [u8; 4] {
+    [
+        opcode as u8,
+        (address >> 16) as u8,
+        (address >> 8) as u8,
+        address as u8,
+    ]
+}
+
 impl<SPI: Transfer<u8>, CS: OutputPin> SingleWrite<u8, u32> for PSRAM<SPI, CS> {
     type Error = Error<SPI, CS>;
     fn try_write(&mut self, address: Address<u32>, word: u8) -> nb::Result<(), Self::Error> {
@@ -237,24 +1355,7 @@ impl<SPI: Transfer<u8>, CS: OutputPin> MultiWrite<u8, u32> for PSRAM<SPI, CS> {
         address: Address<u32>,
         buf: &mut [u8],
     ) -> nb::Result<(), Self::Error> {
-        for (c, chunk) in buf.chunks_mut(256).enumerate() {
-            let current_addr: u32 = (address.0 as usize + c * 256).try_into().unwrap();
-            let mut cmd_buf = [
-                Opcode::Write as u8,
-                (current_addr >> 16) as u8,
-                (current_addr >> 8) as u8,
-                current_addr as u8,
-            ];
-
-            self.cs.try_set_low().map_err(Error::Gpio)?;
-            let mut spi_result = self.spi.try_transfer(&mut cmd_buf);
-            if spi_result.is_ok() {
-                spi_result = self.spi.try_transfer(chunk);
-            }
-            self.cs.try_set_high().map_err(Error::Gpio)?;
-            spi_result.map(|_| ()).map_err(Error::Spi)?;
-        }
-        Ok(())
+        Ok(self.write_slice(address.0, buf)?)
     }
 }
 
@@ -274,22 +1375,7 @@ impl<SPI: Transfer<u8>, CS: OutputPin> MultiRead<u8, u32> for PSRAM<SPI, CS> {
         address: Address<u32>,
         buf: &mut [u8],
     ) -> nb::Result<(), Self::Error> {
-        let mut cmd_buf = [
-            Opcode::Read as u8,
-            (address.0 >> 16) as u8,
-            (address.0 >> 8) as u8,
-            address.0 as u8,
-        ];
-
-        self.cs.try_set_low().map_err(Error::Gpio)?;
-        let mut spi_result = self.spi.try_transfer(&mut cmd_buf);
-        if spi_result.is_ok() {
-            spi_result = self.spi.try_transfer(buf);
-        }
-        self.cs.try_set_high().map_err(Error::Gpio)?;
-        //use nb;
-        spi_result.map(|_| ()).map_err(Error::Spi)?;
-        Ok(())
+        Ok(self.read_slice(address.0, buf)?)
     }
 }
 
@@ -300,13 +1386,36 @@ impl<SPI: Transfer<u8>, CS: OutputPin> StorageSize<u8, u32> for PSRAM<SPI, CS> {
         Ok(Address(0))
     }
 
-    /// 64MB
+    /// Capacity of the device, 8MB for the ESP-PSRAM64
     fn try_total_size(&mut self) -> nb::Result<AddressOffset<u32>, Self::Error> {
-        Ok(AddressOffset(8388608))
+        Ok(AddressOffset(self.settings.device.capacity))
     }
 
-    /// 1KB
-    fn try_page_size(&mut self, _address: Address<u32>) -> nb::Result<AddressOffset<u32>, Self::Error> {
-        Ok(AddressOffset(1024))
+    /// Page size of the device, 1KB for the ESP-PSRAM64
+    fn try_page_size(
+        &mut self,
+        _address: Address<u32>,
+    ) -> nb::Result<AddressOffset<u32>, Self::Error> {
+        Ok(AddressOffset(self.settings.device.page_size))
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<SPI: Transfer<u8>, CS: OutputPin> embedded_storage::ReadStorage for PSRAM<SPI, CS> {
+    type Error = Error<SPI, CS>;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_slice(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        PSRAM::capacity(self) as usize
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<SPI: Transfer<u8>, CS: OutputPin> embedded_storage::Storage for PSRAM<SPI, CS> {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_slice(offset, bytes)
     }
 }
@@ -0,0 +1,721 @@
+use crate::bus::{ClockControl, QuadTransfer};
+use crate::psram::{Freq, Mode, Opcode, ID_LEN, TCEM_NS};
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::OutputPin;
+use std::rc::Rc;
+use std::vec;
+use std::vec::Vec;
+
+#[cfg(feature = "eh1")]
+use embedded_hal_1::spi::{ErrorType, Operation};
+#[cfg(all(feature = "async", not(feature = "eh1")))]
+use embedded_hal_async::spi::{ErrorType, Operation};
+
+/// Size of the simulated memory array, 64Mbit.
+pub const CAPACITY: usize = 8 * 1024 * 1024;
+
+/// Size of a page. Linear bursts wrap inside it unless the clock allows crossing pages.
+pub const PAGE_SIZE: usize = 1024;
+
+/// Size of a line in 32 byte wrap mode.
+const LINE_SIZE: usize = 32;
+
+/// Default Read ID response of the simulated ESP-PSRAM64H: MFID, KGD and the EID
+/// with density 64Mbit.
+const ID: [u8; ID_LEN] = [0x0D, 0x5D, 0x40, 0x00, 0x12, 0x34, 0x56, 0x78];
+
+/// A simulated ESP-PSRAM64 for host testing.
+///
+/// Decodes the command set of the device byte by byte, as clocked in through
+/// `SimSpi`, and keeps 8MB of backing memory. Transactions are framed by
+/// `SimCs`, one-byte commands take effect when CS goes high.
+///
+/// ```
+/// use embedded_hal::storage::{Address, MultiRead, MultiWrite};
+/// use esp_psram::psram::{BurstLength, Freq, PSRAM};
+/// use esp_psram::sim::Simulator;
+///
+/// let sim = Simulator::new(Freq::EightyFour);
+/// let mut psram = PSRAM::init(sim.spi(), sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+/// psram.try_write_slice(Address(0x100), &mut [1, 2, 3]).unwrap();
+///
+/// let mut buf = [0; 3];
+/// sim.peek(0x100, &mut buf);
+/// assert_eq!(buf, [1, 2, 3]);
+/// ```
+///
+/// Linear bursts wrap at the end of the page when the clock is above 84MHz,
+/// and at the end of the 32 byte line in wrap mode, like the real device.
+///
+/// Traffic which breaks the protocol is still carried out as far as the device
+/// would, but is also recorded as a `Violation`:
+///
+/// ```
+/// # use embedded_hal::storage::{Address, MultiRead};
+/// # use esp_psram::psram::{BurstLength, Freq, PSRAM};
+/// # use esp_psram::sim::{Simulator, Violation};
+/// # let sim = Simulator::new(Freq::OneThreeThree);
+/// # let mut psram = PSRAM::init(sim.spi(), sim.cs(), Freq::OneThreeThree, BurstLength::OneKByte).unwrap();
+/// let mut buf = [0; 4000];
+/// psram.try_read_slice(Address(0), &mut buf).unwrap();
+/// assert_eq!(sim.violations(), []);
+///
+/// // CS held low past tCEM
+/// psram.set_tcem(1_000_000);
+/// psram.try_read_slice(Address(0), &mut buf).unwrap();
+/// assert!(matches!(sim.violations()[0], Violation::Tcem { .. }));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    chip: Rc<RefCell<Chip>>,
+}
+
+impl Simulator {
+    /// Creates a simulated device in SPI mode without 32 byte wrapping, clocked at `freq`.
+    ///
+    /// The memory starts out zeroed.
+    pub fn new(freq: Freq) -> Self {
+        Simulator {
+            chip: Rc::new(RefCell::new(Chip::new(freq))),
+        }
+    }
+
+    /// The SPI master the device is attached to.
+    pub fn spi(&self) -> SimSpi {
+        SimSpi {
+            chip: self.chip.clone(),
+        }
+    }
+
+    /// The device as an `embedded-hal` 1.0 `SpiDevice`, which drives CS itself.
+    ///
+    /// For `PsramDevice` and the async driver. Uses the same chip as `spi` and
+    /// `cs`.
+    #[cfg(any(feature = "eh1", feature = "async"))]
+    pub fn device(&self) -> SimDevice {
+        SimDevice {
+            chip: self.chip.clone(),
+        }
+    }
+
+    /// The chip select line of the device.
+    pub fn cs(&self) -> SimCs {
+        SimCs {
+            chip: self.chip.clone(),
+        }
+    }
+
+    /// Copies the memory starting at `address` into `buf`, without any bus traffic.
+    pub fn peek(&self, address: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.chip.borrow().memory[address..address + buf.len()]);
+    }
+
+    /// Copies `buf` into the memory starting at `address`, without any bus traffic.
+    pub fn poke(&self, address: usize, buf: &[u8]) {
+        self.chip.borrow_mut().memory[address..address + buf.len()].copy_from_slice(buf);
+    }
+
+    /// The interface mode the device is in.
+    pub fn mode(&self) -> Mode {
+        self.chip.borrow().mode
+    }
+
+    /// Whether the device is in 32 byte wrap mode.
+    pub fn wrap(&self) -> bool {
+        self.chip.borrow().wrap
+    }
+
+    /// Whether CS is currently low.
+    pub fn selected(&self) -> bool {
+        self.chip.borrow().selected
+    }
+
+    /// The clock the device is running at. Changed through `ClockControl` on `SimSpi`.
+    pub fn freq(&self) -> Freq {
+        self.chip.borrow().freq
+    }
+
+    /// Makes reads above `freq` unreliable, like a board with poor signal
+    /// integrity: the lowest bit of every byte read comes back inverted.
+    /// `None`, the default, keeps all clocks reliable.
+    pub fn set_reliable_freq(&self, freq: Option<Freq>) {
+        self.chip.borrow_mut().reliable_freq = freq;
+    }
+
+    /// Sets the Read ID response: MFID, KGD and the six EID bytes.
+    ///
+    /// Defaults to an ESP-PSRAM64. Only the response changes, the memory stays
+    /// 8MB and the command set stays the same.
+    pub fn set_id(&self, id: [u8; 8]) {
+        self.chip.borrow_mut().id = id;
+    }
+
+    /// Sets the maximum time CS may stay low, in nanoseconds. Defaults to `TCEM_NS`.
+    pub fn set_tcem(&self, nanoseconds: u32) {
+        self.chip.borrow_mut().tcem = nanoseconds;
+    }
+
+    /// The protocol violations recorded so far, oldest first.
+    pub fn violations(&self) -> Vec<Violation> {
+        self.chip.borrow().violations.clone()
+    }
+
+    /// Forgets the recorded violations.
+    pub fn clear_violations(&self) {
+        self.chip.borrow_mut().violations.clear();
+    }
+}
+
+/// A protocol rule broken by the traffic sent to a `Simulator`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+    /// A linear burst ran past the end of a page above 84MHz. The device
+    /// wrapped to the start of the page instead.
+    PageCrossing {
+        /// First address past the page, before wrapping.
+        address: u32,
+    },
+    /// CS stayed low longer than tCEM at the configured clock, so the device
+    /// could not refresh.
+    Tcem {
+        /// Time CS was low, in nanoseconds.
+        nanoseconds: u32,
+    },
+    /// Reset (0x99) was not directly preceded by Reset Enable (0x66) and was ignored.
+    ResetWithoutEnable,
+    /// Read (0x03) was issued above 33MHz.
+    ReadTooFast,
+    /// A command was sent on the lanes of the other mode, or is not available
+    /// in the current mode. The transaction was ignored.
+    WrongMode {
+        /// The command byte.
+        opcode: u8,
+        /// The mode the device was in.
+        mode: Mode,
+    },
+    /// Data was clocked while CS was high.
+    Deselected,
+}
+
+/// The SPI master of a `Simulator`.
+///
+/// Single lane transfers are full duplex, quad transfers are half duplex like
+/// on the real bus.
+#[derive(Debug, Clone)]
+pub struct SimSpi {
+    chip: Rc<RefCell<Chip>>,
+}
+
+impl Transfer<u8> for SimSpi {
+    type Error = Infallible;
+
+    fn try_transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        let mut chip = self.chip.borrow_mut();
+        for word in words.iter_mut() {
+            *word = chip.clock(*word, Lanes::Single);
+        }
+        Ok(words)
+    }
+}
+
+impl Write<u8> for SimSpi {
+    type Error = Infallible;
+
+    fn try_write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        let mut chip = self.chip.borrow_mut();
+        for word in words {
+            chip.clock(*word, Lanes::Single);
+        }
+        Ok(())
+    }
+}
+
+impl ClockControl for SimSpi {
+    fn try_set_freq(&mut self, freq: Freq) -> Result<u32, Self::Error> {
+        self.chip.borrow_mut().freq = freq;
+        Ok(freq.mhz())
+    }
+}
+
+impl QuadTransfer<u8> for SimSpi {
+    fn try_quad_write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        let mut chip = self.chip.borrow_mut();
+        for word in words {
+            chip.clock(*word, Lanes::Quad);
+        }
+        Ok(())
+    }
+
+    fn try_quad_read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let mut chip = self.chip.borrow_mut();
+        for word in words.iter_mut() {
+            *word = chip.clock(0, Lanes::Quad);
+        }
+        Ok(())
+    }
+}
+
+/// A SPI master shared by several simulated devices, for `SharedBus`.
+///
+/// Every byte goes to the device whose CS is low, as only that one listens
+/// and drives MISO. Each device keeps the clock it was created with.
+#[derive(Debug, Clone)]
+pub struct SimBus {
+    chips: Vec<Rc<RefCell<Chip>>>,
+}
+
+impl SimBus {
+    /// Connects `devices` to one bus.
+    pub fn new(devices: &[&Simulator]) -> Self {
+        SimBus {
+            chips: devices.iter().map(|device| device.chip.clone()).collect(),
+        }
+    }
+
+    fn clock(&self, byte: u8, lanes: Lanes) -> u8 {
+        let selected = self.chips.iter().find(|chip| chip.borrow().selected);
+        match selected {
+            Some(chip) => chip.borrow_mut().clock(byte, lanes),
+            None => {
+                // Nobody listens, every device records it
+                for chip in self.chips.iter() {
+                    chip.borrow_mut().clock(byte, lanes);
+                }
+                0xFF
+            }
+        }
+    }
+}
+
+impl Transfer<u8> for SimBus {
+    type Error = Infallible;
+
+    fn try_transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        for word in words.iter_mut() {
+            *word = self.clock(*word, Lanes::Single);
+        }
+        Ok(words)
+    }
+}
+
+impl Write<u8> for SimBus {
+    type Error = Infallible;
+
+    fn try_write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        for word in words {
+            self.clock(*word, Lanes::Single);
+        }
+        Ok(())
+    }
+}
+
+impl QuadTransfer<u8> for SimBus {
+    fn try_quad_write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        for word in words {
+            self.clock(*word, Lanes::Quad);
+        }
+        Ok(())
+    }
+
+    fn try_quad_read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        for word in words.iter_mut() {
+            *word = self.clock(0, Lanes::Quad);
+        }
+        Ok(())
+    }
+}
+
+/// The chip select line of a `Simulator`. Active low.
+#[derive(Debug, Clone)]
+pub struct SimCs {
+    chip: Rc<RefCell<Chip>>,
+}
+
+impl OutputPin for SimCs {
+    type Error = Infallible;
+
+    fn try_set_low(&mut self) -> Result<(), Self::Error> {
+        self.chip.borrow_mut().select();
+        Ok(())
+    }
+
+    fn try_set_high(&mut self) -> Result<(), Self::Error> {
+        self.chip.borrow_mut().deselect();
+        Ok(())
+    }
+}
+
+/// The `SpiDevice` of a `Simulator`.
+///
+/// Every transaction selects the device, runs its operations on a single lane
+/// and deselects it again. Delays keep CS low and count towards tCEM.
+#[cfg(any(feature = "eh1", feature = "async"))]
+#[derive(Debug, Clone)]
+pub struct SimDevice {
+    chip: Rc<RefCell<Chip>>,
+}
+
+#[cfg(any(feature = "eh1", feature = "async"))]
+impl SimDevice {
+    fn run(&mut self, operations: &mut [Operation<'_, u8>]) {
+        let mut chip = self.chip.borrow_mut();
+        chip.select();
+        for operation in operations.iter_mut() {
+            match operation {
+                Operation::Read(words) => {
+                    for word in words.iter_mut() {
+                        *word = chip.clock(0, Lanes::Single);
+                    }
+                }
+                Operation::Write(words) => {
+                    for word in words.iter() {
+                        chip.clock(*word, Lanes::Single);
+                    }
+                }
+                Operation::Transfer(read, write) => {
+                    for index in 0..read.len().max(write.len()) {
+                        let out = chip.clock(write.get(index).copied().unwrap_or(0), Lanes::Single);
+                        if let Some(word) = read.get_mut(index) {
+                            *word = out;
+                        }
+                    }
+                }
+                Operation::TransferInPlace(words) => {
+                    for word in words.iter_mut() {
+                        *word = chip.clock(*word, Lanes::Single);
+                    }
+                }
+                Operation::DelayNs(nanoseconds) => chip.wait(*nanoseconds),
+            }
+        }
+        chip.deselect();
+    }
+}
+
+#[cfg(any(feature = "eh1", feature = "async"))]
+impl ErrorType for SimDevice {
+    type Error = Infallible;
+}
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::spi::SpiDevice for SimDevice {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        self.run(operations);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::spi::SpiDevice for SimDevice {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        self.run(operations);
+        Ok(())
+    }
+}
+
+/// Lanes a byte was clocked on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lanes {
+    Single,
+    Quad,
+}
+
+/// Position of the device within a transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Waiting for the command byte.
+    Command,
+    /// Collecting the 24 bit address.
+    Address {
+        opcode: Opcode,
+        address: u32,
+        count: u8,
+    },
+    /// Clocking through the wait cycles.
+    Wait {
+        opcode: Opcode,
+        address: u32,
+        left: usize,
+    },
+    /// Moving data to or from the memory.
+    Data {
+        opcode: Opcode,
+        address: u32,
+        /// The last byte was at the end of a page.
+        page_end: bool,
+    },
+    /// Sending the Read ID response.
+    Id { index: usize },
+    /// A one-byte command, run when CS goes high.
+    Done { opcode: Opcode },
+    /// Unknown command, the rest of the transaction is ignored.
+    Ignore,
+}
+
+#[derive(Debug)]
+struct Chip {
+    memory: Vec<u8>,
+    freq: Freq,
+    mode: Mode,
+    wrap: bool,
+    reset_enabled: bool,
+    selected: bool,
+    state: State,
+    tcem: u32,
+    reliable_freq: Option<Freq>,
+    id: [u8; ID_LEN],
+    /// Clocks since CS went low.
+    clocks: u32,
+    violations: Vec<Violation>,
+}
+
+impl Chip {
+    fn new(freq: Freq) -> Self {
+        Chip {
+            memory: vec![0; CAPACITY],
+            freq,
+            mode: Mode::Spi,
+            wrap: false,
+            reset_enabled: false,
+            selected: false,
+            state: State::Command,
+            tcem: TCEM_NS,
+            reliable_freq: None,
+            id: ID,
+            clocks: 0,
+            violations: Vec::new(),
+        }
+    }
+
+    fn select(&mut self) {
+        if !self.selected {
+            self.selected = true;
+            self.state = State::Command;
+            self.clocks = 0;
+        }
+    }
+
+    fn deselect(&mut self) {
+        if !self.selected {
+            return;
+        }
+        self.selected = false;
+
+        let mhz = self.freq.mhz();
+        if u64::from(self.clocks) * 1000 > u64::from(self.tcem) * u64::from(mhz) {
+            self.violations.push(Violation::Tcem {
+                nanoseconds: self.clocks * 1000 / mhz,
+            });
+        }
+
+        let opcode = match self.state {
+            State::Command => return,
+            State::Address { opcode, .. }
+            | State::Wait { opcode, .. }
+            | State::Data { opcode, .. }
+            | State::Done { opcode } => Some(opcode),
+            State::Id { .. } => Some(Opcode::ReadID),
+            State::Ignore => None,
+        };
+        self.state = State::Command;
+
+        // Reset has to follow Reset Enable directly
+        let reset_enabled = self.reset_enabled;
+        self.reset_enabled = false;
+        match opcode {
+            Some(Opcode::EnterQuadMode) => self.mode = Mode::Qpi,
+            Some(Opcode::ExitQuadMode) => self.mode = Mode::Spi,
+            Some(Opcode::SetBurstLength) => self.wrap = !self.wrap,
+            Some(Opcode::ResetEnable) => self.reset_enabled = true,
+            Some(Opcode::Reset) if reset_enabled => {
+                self.mode = Mode::Spi;
+                self.wrap = false;
+            }
+            Some(Opcode::Reset) => self.violations.push(Violation::ResetWithoutEnable),
+            _ => {}
+        }
+    }
+
+    /// Lets time pass without clocking, CS stays as it is.
+    #[cfg(any(feature = "eh1", feature = "async"))]
+    fn wait(&mut self, nanoseconds: u32) {
+        if self.selected {
+            let clocks =
+                u64::from(self.clocks) + u64::from(nanoseconds) * u64::from(self.freq.mhz()) / 1000;
+            self.clocks = clocks.min(u64::from(u32::MAX)) as u32;
+        }
+    }
+
+    /// Clocks one byte in and returns the byte the device drives back.
+    fn clock(&mut self, byte: u8, lanes: Lanes) -> u8 {
+        if !self.selected {
+            self.violations.push(Violation::Deselected);
+            return 0xFF;
+        }
+
+        self.clocks += match lanes {
+            Lanes::Single => 8,
+            Lanes::Quad => 2,
+        };
+
+        match self.state {
+            State::Command => {
+                let opcode = Opcode::from_u8(byte);
+                if !self.available(opcode, lanes) {
+                    self.violations.push(Violation::WrongMode {
+                        opcode: byte,
+                        mode: self.mode,
+                    });
+                    self.state = State::Ignore;
+                    return 0xFF;
+                }
+
+                self.state = match opcode {
+                    Some(opcode) if opcode.has_address() => State::Address {
+                        opcode,
+                        address: 0,
+                        count: 0,
+                    },
+                    Some(opcode) => State::Done { opcode },
+                    None => State::Ignore,
+                };
+                if opcode == Some(Opcode::Read) && self.freq.mhz() > 33 {
+                    self.violations.push(Violation::ReadTooFast);
+                }
+                0xFF
+            }
+            State::Address {
+                opcode,
+                address,
+                count,
+            } => {
+                let address = (address << 8) | u32::from(byte);
+                self.state = if count < 2 {
+                    State::Address {
+                        opcode,
+                        address,
+                        count: count + 1,
+                    }
+                } else {
+                    self.after_address(opcode, address)
+                };
+                0xFF
+            }
+            State::Wait {
+                opcode,
+                address,
+                left,
+            } => {
+                self.state = if left > 1 {
+                    State::Wait {
+                        opcode,
+                        address,
+                        left: left - 1,
+                    }
+                } else {
+                    State::Data {
+                        opcode,
+                        address,
+                        page_end: false,
+                    }
+                };
+                0xFF
+            }
+            State::Data {
+                opcode,
+                address,
+                page_end,
+            } => {
+                if page_end && !self.freq.crosses_pages() {
+                    self.violations.push(Violation::PageCrossing {
+                        address: (address & !(PAGE_SIZE as u32 - 1)) + PAGE_SIZE as u32,
+                    });
+                }
+
+                let index = address as usize % CAPACITY;
+                let out = match opcode {
+                    Opcode::Write | Opcode::QuadWrite => {
+                        self.memory[index] = byte;
+                        0xFF
+                    }
+                    _ => self.memory[index] ^ self.read_noise(),
+                };
+                self.state = State::Data {
+                    opcode,
+                    address: self.next(address),
+                    page_end: !self.wrap && address as usize % PAGE_SIZE == PAGE_SIZE - 1,
+                };
+                out
+            }
+            State::Id { index } => {
+                self.state = State::Id { index: index + 1 };
+                self.id[index % ID_LEN]
+            }
+            State::Done { .. } | State::Ignore => 0xFF,
+        }
+    }
+
+    /// Bits flipped in data read at the current clock.
+    fn read_noise(&self) -> u8 {
+        match self.reliable_freq {
+            Some(reliable) if self.freq.mhz() > reliable.mhz() => 0x01,
+            _ => 0x00,
+        }
+    }
+
+    /// The state once the address phase of `opcode` is complete.
+    fn after_address(&self, opcode: Opcode, address: u32) -> State {
+        let wait = opcode.wait_bytes(self.mode == Mode::Qpi);
+
+        if opcode == Opcode::ReadID {
+            State::Id { index: 0 }
+        } else if wait > 0 {
+            State::Wait {
+                opcode,
+                address,
+                left: wait,
+            }
+        } else {
+            State::Data {
+                opcode,
+                address,
+                page_end: false,
+            }
+        }
+    }
+
+    /// Whether a command clocked on `lanes` is accepted in the current mode.
+    /// Unknown commands are only checked for the lanes.
+    fn available(&self, opcode: Option<Opcode>, lanes: Lanes) -> bool {
+        match self.mode {
+            Mode::Spi => lanes == Lanes::Single && opcode != Some(Opcode::ExitQuadMode),
+            Mode::Qpi => {
+                lanes == Lanes::Quad
+                    && !matches!(
+                        opcode,
+                        Some(Opcode::Read) | Some(Opcode::ReadID) | Some(Opcode::EnterQuadMode)
+                    )
+            }
+        }
+    }
+
+    /// The address following `address` within a burst.
+    fn next(&self, address: u32) -> u32 {
+        let boundary = if self.wrap {
+            LINE_SIZE
+        } else if !self.freq.crosses_pages() {
+            PAGE_SIZE
+        } else {
+            CAPACITY
+        } as u32;
+
+        let base = address - address % boundary;
+        base + (address + 1) % boundary
+    }
+}
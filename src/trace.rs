@@ -0,0 +1,300 @@
+use crate::bus::QuadTransfer;
+use crate::psram::Opcode;
+
+use core::cell::RefCell;
+use core::fmt;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::OutputPin;
+
+/// One CS framed transaction, decoded.
+///
+/// Formats like `WRITE addr=0x001234 len=256` or `READ_ID`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    /// The command byte.
+    pub opcode: u8,
+    /// The command byte was sent on four lanes, i.e. the device was in QPI mode.
+    pub quad: bool,
+    /// The 24 bit address, for commands which take one.
+    pub address: Option<u32>,
+    /// Number of data bytes after the command, address and wait phases.
+    pub len: usize,
+}
+
+impl Record {
+    fn command(&self) -> Option<Opcode> {
+        Opcode::from_u8(self.opcode)
+    }
+
+    /// Whether the address and length are worth showing. The Read ID address
+    /// and response length are fixed.
+    fn memory_access(&self) -> bool {
+        match self.command() {
+            Some(Opcode::ReadID) | None => false,
+            Some(opcode) => opcode.has_address(),
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.command() {
+            Some(opcode) => f.write_str(opcode.name())?,
+            None => write!(f, "UNKNOWN(0x{:02X})", self.opcode)?,
+        }
+
+        if self.memory_access() {
+            if let Some(address) = self.address {
+                write!(f, " addr=0x{:06X}", address)?;
+            }
+            write!(f, " len={}", self.len)?;
+        }
+
+        if self.quad {
+            f.write_str(" qpi")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Record {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        match self.command() {
+            Some(opcode) => defmt::write!(f, "{=str}", opcode.name()),
+            None => defmt::write!(f, "UNKNOWN({=u8:#04x})", self.opcode),
+        }
+
+        if self.memory_access() {
+            if let Some(address) = self.address {
+                defmt::write!(f, " addr={=u32:#08x}", address);
+            }
+            defmt::write!(f, " len={=usize}", self.len);
+        }
+
+        if self.quad {
+            defmt::write!(f, " qpi");
+        }
+    }
+}
+
+/// Records every transaction the driver sends and passes it to a sink.
+///
+/// The SPI master and CS pin are wrapped with `spi` and `cs`, and the wrappers
+/// are handed to the driver in place of the originals:
+///
+/// ```
+/// # #[cfg(feature = "sim")]
+/// # fn main() {
+/// # use esp_psram::psram::{BurstLength, Freq, PSRAM};
+/// # use esp_psram::sim::Simulator;
+/// # let sim = Simulator::new(Freq::EightyFour);
+/// # let (spi, cs) = (sim.spi(), sim.cs());
+/// use esp_psram::trace::{Record, Trace};
+///
+/// let mut log = Vec::new();
+/// let trace = Trace::new(|record: &Record| log.push(record.to_string()));
+/// let mut psram = PSRAM::init(trace.spi(spi), trace.cs(cs), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+/// psram.read_id().unwrap();
+///
+/// drop(psram);
+/// drop(trace);
+/// assert_eq!(log, ["READ_ID"]);
+/// # }
+/// # #[cfg(not(feature = "sim"))]
+/// # fn main() {}
+/// ```
+///
+/// A record is passed to the sink when CS goes high. Bytes clocked while CS is
+/// high are not recorded.
+#[derive(Debug)]
+pub struct Trace<F: FnMut(&Record)> {
+    recorder: RefCell<Recorder<F>>,
+}
+
+impl<F: FnMut(&Record)> Trace<F> {
+    /// Creates a trace which calls `sink` once per transaction.
+    pub fn new(sink: F) -> Self {
+        Trace {
+            recorder: RefCell::new(Recorder {
+                sink,
+                selected: false,
+                quad: false,
+                header: [0; 4],
+                count: 0,
+            }),
+        }
+    }
+
+    /// Wraps the SPI master.
+    pub fn spi<SPI>(&self, spi: SPI) -> TraceSpi<'_, SPI, F> {
+        TraceSpi {
+            spi,
+            recorder: &self.recorder,
+        }
+    }
+
+    /// Wraps the CS pin.
+    pub fn cs<CS>(&self, cs: CS) -> TraceCs<'_, CS, F> {
+        TraceCs {
+            cs,
+            recorder: &self.recorder,
+        }
+    }
+
+    /// Returns the sink. All wrappers must have been dropped.
+    pub fn into_sink(self) -> F {
+        self.recorder.into_inner().sink
+    }
+}
+
+#[derive(Debug)]
+struct Recorder<F> {
+    sink: F,
+    selected: bool,
+    /// The first byte was sent on four lanes.
+    quad: bool,
+    /// Command and address bytes.
+    header: [u8; 4],
+    /// All bytes clocked since CS went low.
+    count: usize,
+}
+
+impl<F: FnMut(&Record)> Recorder<F> {
+    fn select(&mut self) {
+        if !self.selected {
+            self.selected = true;
+            self.count = 0;
+        }
+    }
+
+    fn clock(&mut self, bytes: &[u8], quad: bool) {
+        if !self.selected {
+            return;
+        }
+
+        if self.count == 0 && !bytes.is_empty() {
+            self.quad = quad;
+        }
+        for byte in bytes {
+            if self.count < self.header.len() {
+                self.header[self.count] = *byte;
+            }
+            self.count += 1;
+        }
+    }
+
+    /// Clocks `len` bytes whose value does not matter, like quad reads.
+    fn skip(&mut self, len: usize, quad: bool) {
+        if self.selected && len > 0 {
+            self.clock(&[0], quad);
+            self.count += len - 1;
+        }
+    }
+
+    fn deselect(&mut self) {
+        if !self.selected {
+            return;
+        }
+        self.selected = false;
+
+        if self.count == 0 {
+            return;
+        }
+
+        let opcode = self.header[0];
+        let mut record = Record {
+            opcode,
+            quad: self.quad,
+            address: None,
+            len: self.count - 1,
+        };
+
+        if let Some(command) = Opcode::from_u8(opcode).filter(|command| command.has_address()) {
+            if self.count >= self.header.len() {
+                let [_, high, middle, low] = self.header;
+                record.address = Some(u32::from_be_bytes([0, high, middle, low]));
+            }
+            record.len = self
+                .count
+                .saturating_sub(self.header.len() + command.wait_bytes(self.quad));
+        }
+
+        (self.sink)(&record);
+    }
+}
+
+/// The SPI master of a `Trace`.
+#[derive(Debug)]
+pub struct TraceSpi<'a, SPI, F> {
+    spi: SPI,
+    recorder: &'a RefCell<Recorder<F>>,
+}
+
+impl<'a, SPI, F> TraceSpi<'a, SPI, F> {
+    /// Returns the wrapped SPI master.
+    pub fn into_inner(self) -> SPI {
+        self.spi
+    }
+}
+
+impl<'a, SPI: Transfer<u8>, F: FnMut(&Record)> Transfer<u8> for TraceSpi<'a, SPI, F> {
+    type Error = SPI::Error;
+
+    fn try_transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        // Transfer overwrites the words, record them first
+        self.recorder.borrow_mut().clock(words, false);
+        self.spi.try_transfer(words)
+    }
+}
+
+impl<'a, SPI: Write<u8>, F: FnMut(&Record)> Write<u8> for TraceSpi<'a, SPI, F> {
+    type Error = SPI::Error;
+
+    fn try_write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.recorder.borrow_mut().clock(words, false);
+        self.spi.try_write(words)
+    }
+}
+
+impl<'a, SPI: QuadTransfer<u8>, F: FnMut(&Record)> QuadTransfer<u8> for TraceSpi<'a, SPI, F> {
+    fn try_quad_write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.recorder.borrow_mut().clock(words, true);
+        self.spi.try_quad_write(words)
+    }
+
+    fn try_quad_read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.recorder.borrow_mut().skip(words.len(), true);
+        self.spi.try_quad_read(words)
+    }
+}
+
+/// The CS pin of a `Trace`.
+#[derive(Debug)]
+pub struct TraceCs<'a, CS, F> {
+    cs: CS,
+    recorder: &'a RefCell<Recorder<F>>,
+}
+
+impl<'a, CS, F> TraceCs<'a, CS, F> {
+    /// Returns the wrapped CS pin.
+    pub fn into_inner(self) -> CS {
+        self.cs
+    }
+}
+
+impl<'a, CS: OutputPin, F: FnMut(&Record)> OutputPin for TraceCs<'a, CS, F> {
+    type Error = CS::Error;
+
+    fn try_set_low(&mut self) -> Result<(), Self::Error> {
+        self.cs.try_set_low()?;
+        self.recorder.borrow_mut().select();
+        Ok(())
+    }
+
+    fn try_set_high(&mut self) -> Result<(), Self::Error> {
+        self.cs.try_set_high()?;
+        self.recorder.borrow_mut().deselect();
+        Ok(())
+    }
+}
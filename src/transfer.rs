@@ -0,0 +1,150 @@
+use crate::psram::{Payload, PSRAM};
+use crate::Error;
+
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::OutputPin;
+
+/// A single lane read that moves forward one transaction per poll.
+///
+/// Each call to `poll` runs the next transaction the planner allows and
+/// returns `nb::Error::WouldBlock` until the whole buffer has been read, so a
+/// superloop can do other work between transactions:
+///
+/// ```
+/// # #[cfg(feature = "sim")]
+/// # fn main() {
+/// # use esp_psram::psram::{BurstLength, Freq, PSRAM};
+/// # use esp_psram::sim::Simulator;
+/// # let sim = Simulator::new(Freq::EightyFour);
+/// # let mut psram = PSRAM::init(sim.spi(), sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+/// # let mut buf = [0; 4096];
+/// # let do_other_work = || {};
+/// use esp_psram::transfer::ReadTransfer;
+///
+/// let mut read = ReadTransfer::new(0x1000, &mut buf);
+/// loop {
+///     match read.poll(&mut psram) {
+///         Err(nb::Error::WouldBlock) => do_other_work(),
+///         result => break result,
+///     }
+/// }
+/// # .unwrap();
+/// # }
+/// # #[cfg(not(feature = "sim"))]
+/// # fn main() {}
+/// ```
+///
+/// Other accesses to the device may happen between polls. The transfer does
+/// not hold the driver, so it can't notice them.
+#[derive(Debug)]
+pub struct ReadTransfer<'a> {
+    address: u32,
+    buf: &'a mut [u8],
+    done: usize,
+}
+
+impl<'a> ReadTransfer<'a> {
+    /// Prepares a read of `buf.len()` bytes starting at `address`. Nothing is sent yet.
+    pub fn new(address: u32, buf: &'a mut [u8]) -> Self {
+        ReadTransfer {
+            address,
+            buf,
+            done: 0,
+        }
+    }
+
+    /// Runs the next transaction.
+    ///
+    /// Returns `Ok(())` once the whole buffer has been read. After an error the
+    /// failed transaction is retried by the next poll.
+    pub fn poll<SPI: Transfer<u8>, CS: OutputPin>(
+        &mut self,
+        psram: &mut PSRAM<SPI, CS>,
+    ) -> nb::Result<(), Error<SPI, CS>> {
+        if self.is_done() {
+            return Ok(());
+        }
+
+        let address = self.address + self.done as u32;
+        self.done += psram.step(address, Payload::Read(&mut self.buf[self.done..]))?;
+
+        if self.is_done() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// The number of bytes read so far.
+    pub fn progress(&self) -> usize {
+        self.done
+    }
+
+    /// Whether the whole buffer has been read.
+    pub fn is_done(&self) -> bool {
+        self.done == self.buf.len()
+    }
+
+    /// Gives the buffer back, even if the transfer has not finished.
+    pub fn into_inner(self) -> &'a mut [u8] {
+        self.buf
+    }
+}
+
+/// A single lane write that moves forward one transaction per poll.
+///
+/// Works like `ReadTransfer`.
+#[derive(Debug)]
+pub struct WriteTransfer<'a> {
+    address: u32,
+    buf: &'a [u8],
+    done: usize,
+}
+
+impl<'a> WriteTransfer<'a> {
+    /// Prepares a write of `buf` starting at `address`. Nothing is sent yet.
+    pub fn new(address: u32, buf: &'a [u8]) -> Self {
+        WriteTransfer {
+            address,
+            buf,
+            done: 0,
+        }
+    }
+
+    /// Runs the next transaction.
+    ///
+    /// Returns `Ok(())` once the whole buffer has been written. After an error
+    /// the failed transaction is retried by the next poll.
+    pub fn poll<SPI: Transfer<u8>, CS: OutputPin>(
+        &mut self,
+        psram: &mut PSRAM<SPI, CS>,
+    ) -> nb::Result<(), Error<SPI, CS>> {
+        if self.is_done() {
+            return Ok(());
+        }
+
+        let address = self.address + self.done as u32;
+        self.done += psram.step(address, Payload::Write(&self.buf[self.done..]))?;
+
+        if self.is_done() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// The number of bytes written so far.
+    pub fn progress(&self) -> usize {
+        self.done
+    }
+
+    /// Whether the whole buffer has been written.
+    pub fn is_done(&self) -> bool {
+        self.done == self.buf.len()
+    }
+
+    /// Gives the buffer back, even if the transfer has not finished.
+    pub fn into_inner(self) -> &'a [u8] {
+        self.buf
+    }
+}
@@ -0,0 +1,264 @@
+use crate::heap::{AllocError, Heap, PsramAlloc};
+use crate::psram::PSRAM;
+use crate::Error;
+
+use bytemuck::Pod;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::OutputPin;
+
+/// A `Vec`-like array whose elements are stored on the device.
+///
+/// The length and capacity are kept in MCU RAM, the elements in a region of
+/// the device. The capacity is fixed by the size of the region; pushing past
+/// it returns `Error::OutOfBounds`. Like `PsramBox`, `T` has to be plain old
+/// data.
+///
+/// ```
+/// # #[cfg(feature = "sim")]
+/// # fn main() {
+/// # use esp_psram::psram::{BurstLength, Freq, PSRAM};
+/// # use esp_psram::sim::Simulator;
+/// # let sim = Simulator::new(Freq::EightyFour);
+/// # let mut psram = PSRAM::init(sim.spi(), sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+/// # let mut heap: esp_psram::heap::Heap<8> = esp_psram::heap::Heap::new(0, psram.capacity());
+/// # let adc_buf = [3u16; 256];
+/// use esp_psram::vec::PsramVec;
+///
+/// let mut samples: PsramVec<u16> = PsramVec::with_capacity(&mut heap, 100_000).unwrap();
+/// samples.extend_from_slice(&mut psram, &adc_buf).unwrap();
+/// let mut sum = 0u32;
+/// for sample in samples.iter::<64, _, _>(&mut psram) {
+///     sum += u32::from(sample.unwrap());
+/// }
+/// assert_eq!(sum, 3 * 256);
+/// # }
+/// # #[cfg(not(feature = "sim"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct PsramVec<T: Pod> {
+    region: PsramAlloc,
+    len: usize,
+    _elements: PhantomData<T>,
+}
+
+impl<T: Pod> PsramVec<T> {
+    /// Creates an empty array in `region`, holding as many elements as fit.
+    ///
+    /// Panics if `T` is zero sized.
+    pub fn new(region: PsramAlloc) -> Self {
+        assert!(size_of::<T>() > 0, "zero sized elements are not supported");
+        PsramVec {
+            region,
+            len: 0,
+            _elements: PhantomData,
+        }
+    }
+
+    /// Creates an empty array with room for `capacity` elements, allocated from `heap`.
+    pub fn with_capacity<const N: usize>(
+        heap: &mut Heap<N>,
+        capacity: usize,
+    ) -> Result<Self, AllocError> {
+        let len = capacity
+            .checked_mul(size_of::<T>())
+            .filter(|len| *len <= u32::MAX as usize)
+            .ok_or(AllocError::OutOfMemory)?;
+        Ok(Self::new(heap.allocate(len as u32)?))
+    }
+
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of elements the region can hold.
+    pub fn capacity(&self) -> usize {
+        self.region.len() as usize / size_of::<T>()
+    }
+
+    /// Appends `value`. Returns `Error::OutOfBounds` if the array is full.
+    pub fn push<SPI: Transfer<u8>, CS: OutputPin>(
+        &mut self,
+        psram: &mut PSRAM<SPI, CS>,
+        value: T,
+    ) -> Result<(), Error<SPI, CS>> {
+        self.extend_from_slice(psram, &[value])
+    }
+
+    /// Removes the last element and returns it, or `None` if the array is empty.
+    pub fn pop<SPI: Transfer<u8>, CS: OutputPin>(
+        &mut self,
+        psram: &mut PSRAM<SPI, CS>,
+    ) -> Result<Option<T>, Error<SPI, CS>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        let value = self.load(psram, self.len - 1)?;
+        self.len -= 1;
+        Ok(Some(value))
+    }
+
+    /// Reads the element at `index`, or `None` if it is out of bounds.
+    pub fn get<SPI: Transfer<u8>, CS: OutputPin>(
+        &self,
+        psram: &mut PSRAM<SPI, CS>,
+        index: usize,
+    ) -> Result<Option<T>, Error<SPI, CS>> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        self.load(psram, index).map(Some)
+    }
+
+    /// Overwrites the element at `index`. Returns `Error::OutOfBounds` if it does not exist.
+    pub fn set<SPI: Transfer<u8>, CS: OutputPin>(
+        &mut self,
+        psram: &mut PSRAM<SPI, CS>,
+        index: usize,
+        value: T,
+    ) -> Result<(), Error<SPI, CS>> {
+        if index >= self.len {
+            return Err(Error::OutOfBounds);
+        }
+        self.region
+            .write(psram, Self::offset(index), bytemuck::bytes_of(&value))
+    }
+
+    /// Appends all of `values` in one write. Returns `Error::OutOfBounds`
+    /// without writing anything if they don't all fit.
+    pub fn extend_from_slice<SPI: Transfer<u8>, CS: OutputPin>(
+        &mut self,
+        psram: &mut PSRAM<SPI, CS>,
+        values: &[T],
+    ) -> Result<(), Error<SPI, CS>> {
+        if values.len() > self.capacity() - self.len {
+            return Err(Error::OutOfBounds);
+        }
+        self.region
+            .write(psram, Self::offset(self.len), bytemuck::cast_slice(values))?;
+        self.len += values.len();
+        Ok(())
+    }
+
+    /// Reads the elements starting at `index` into `buf`, in one read.
+    ///
+    /// Returns `Error::OutOfBounds` if that reaches past the last element.
+    pub fn read_into<SPI: Transfer<u8>, CS: OutputPin>(
+        &self,
+        psram: &mut PSRAM<SPI, CS>,
+        index: usize,
+        buf: &mut [T],
+    ) -> Result<(), Error<SPI, CS>> {
+        if index > self.len || buf.len() > self.len - index {
+            return Err(Error::OutOfBounds);
+        }
+        self.region
+            .read(psram, Self::offset(index), bytemuck::cast_slice_mut(buf))
+    }
+
+    /// Shortens the array to `len` elements. Does nothing if it is already shorter.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// Removes all elements.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Iterates over the elements, reading `C` of them per burst.
+    ///
+    /// The iterator stops after the first error. Panics if `C` is zero.
+    pub fn iter<'a, const C: usize, SPI: Transfer<u8>, CS: OutputPin>(
+        &'a self,
+        psram: &'a mut PSRAM<SPI, CS>,
+    ) -> Iter<'a, T, SPI, CS, C> {
+        assert!(C > 0, "the burst must hold at least one element");
+        Iter {
+            vec: self,
+            psram,
+            buf: [T::zeroed(); C],
+            index: 0,
+            buffered: 0..0,
+        }
+    }
+
+    /// The region the elements are stored in.
+    pub fn region(&self) -> &PsramAlloc {
+        &self.region
+    }
+
+    /// Gives up the array and returns its region, for example to free it.
+    pub fn into_region(self) -> PsramAlloc {
+        self.region
+    }
+
+    fn load<SPI: Transfer<u8>, CS: OutputPin>(
+        &self,
+        psram: &mut PSRAM<SPI, CS>,
+        index: usize,
+    ) -> Result<T, Error<SPI, CS>> {
+        let mut value = T::zeroed();
+        self.region.read(
+            psram,
+            Self::offset(index),
+            bytemuck::bytes_of_mut(&mut value),
+        )?;
+        Ok(value)
+    }
+
+    /// Byte offset of element `index` within the region. Only called with
+    /// indices below the capacity, so it fits the region length.
+    fn offset(index: usize) -> u32 {
+        (index * size_of::<T>()) as u32
+    }
+}
+
+/// Iterator over a `PsramVec` which reads the elements in bursts, see `PsramVec::iter`.
+pub struct Iter<'a, T: Pod, SPI: Transfer<u8>, CS: OutputPin, const C: usize> {
+    vec: &'a PsramVec<T>,
+    psram: &'a mut PSRAM<SPI, CS>,
+    buf: [T; C],
+    /// Index of the next element to read from the device.
+    index: usize,
+    /// Part of `buf` not handed out yet.
+    buffered: core::ops::Range<usize>,
+}
+
+impl<'a, T: Pod, SPI: Transfer<u8>, CS: OutputPin, const C: usize> Iterator
+    for Iter<'a, T, SPI, CS, C>
+{
+    type Item = Result<T, Error<SPI, CS>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffered.is_empty() {
+            let count = C.min(self.vec.len - self.index);
+            if count == 0 {
+                return None;
+            }
+
+            if let Err(e) = self
+                .vec
+                .read_into(self.psram, self.index, &mut self.buf[..count])
+            {
+                // Stop after the error
+                self.index = self.vec.len;
+                return Some(Err(e));
+            }
+            self.index += count;
+            self.buffered = 0..count;
+        }
+
+        let value = self.buf[self.buffered.start];
+        self.buffered.start += 1;
+        Some(Ok(value))
+    }
+}
@@ -0,0 +1,145 @@
+use core::future::Future;
+use embedded_hal_async::delay::DelayNs;
+use esp_psram::asynch::PsramDevice;
+use esp_psram::psram::{BurstLength, Freq};
+use esp_psram::sim::{SimDevice, Simulator, CAPACITY};
+use esp_psram::DeviceError;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoDelay;
+
+impl DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+struct NoWake;
+
+impl Wake for NoWake {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Runs `future` to completion. The simulator never makes it wait.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(NoWake));
+    let mut context = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+fn pattern(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(13) ^ (i >> 8) ^ seed) as u8)
+        .collect()
+}
+
+fn init(freq: Freq, burst: BurstLength) -> (Simulator, PsramDevice<SimDevice>) {
+    let sim = Simulator::new(freq);
+    let psram = block_on(PsramDevice::init_with_delay(
+        sim.device(),
+        freq,
+        burst,
+        &mut NoDelay,
+    ))
+    .unwrap();
+    (sim, psram)
+}
+
+#[test]
+fn round_trip() {
+    let setups = [
+        (Freq::ThreeThree, BurstLength::None),
+        (Freq::EightyFour, BurstLength::ThirtyTwoByte),
+        (Freq::OneThreeThree, BurstLength::OneKByte),
+    ];
+    for &(freq, burst) in setups.iter() {
+        let (sim, mut psram) = init(freq, burst);
+        assert_eq!(sim.wrap(), burst == BurstLength::ThirtyTwoByte);
+        assert!(block_on(psram.read_id()).unwrap().matched);
+
+        let data = pattern(5000, 1);
+        block_on(psram.write(1000, &data)).unwrap();
+        let mut memory = vec![0; 5000];
+        sim.peek(1000, &mut memory);
+        assert_eq!(memory, data);
+
+        let mut buf = vec![0; 5000];
+        block_on(psram.read(1000, &mut buf)).unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(sim.violations(), [], "{:?} {:?}", freq, burst);
+    }
+}
+
+#[test]
+fn read_and_write_wrapped() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::ThirtyTwoByte);
+    let mut data = [0; 32];
+    data.copy_from_slice(&pattern(32, 2));
+    block_on(psram.write_wrapped(0x21F, &data)).unwrap();
+    let mut memory = [0; 32];
+    sim.peek(0x200, &mut memory);
+    for (i, byte) in data.iter().enumerate() {
+        assert_eq!(memory[(0x1F + i) % 32], *byte);
+    }
+
+    let mut line = [0; 32];
+    block_on(psram.read_wrapped(0x21F, &mut line)).unwrap();
+    assert_eq!(line, data);
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn accesses_past_the_end_are_refused() {
+    let (sim, mut psram) = init(Freq::OneZeroFour, BurstLength::ThirtyTwoByte);
+    let end = CAPACITY as u32;
+    let mut buf = [0; 8];
+    assert!(matches!(
+        block_on(psram.read(end - 4, &mut buf)),
+        Err(DeviceError::OutOfBounds)
+    ));
+    assert!(matches!(
+        block_on(psram.write(u32::MAX - 2, &buf)),
+        Err(DeviceError::OutOfBounds)
+    ));
+    let mut line = [0; 32];
+    assert!(matches!(
+        block_on(psram.read_wrapped(end, &mut line)),
+        Err(DeviceError::OutOfBounds)
+    ));
+    assert_eq!(sim.violations(), []);
+}
+
+#[cfg(feature = "embedded-storage-async")]
+#[test]
+fn erase_fills_with_ones() {
+    use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    sim.poke(0, &[0x11; 300]);
+    block_on(NorFlash::erase(&mut psram, 10, 210)).unwrap();
+
+    let mut memory = [0; 300];
+    sim.peek(0, &mut memory);
+    assert!(memory[..10].iter().all(|&byte| byte == 0x11));
+    assert!(memory[10..210].iter().all(|&byte| byte == 0xFF));
+    assert!(memory[210..].iter().all(|&byte| byte == 0x11));
+
+    let mut buf = [0; 4];
+    block_on(ReadNorFlash::read(&mut psram, 208, &mut buf)).unwrap();
+    assert_eq!(buf, [0xFF, 0xFF, 0x11, 0x11]);
+    assert_eq!(ReadNorFlash::capacity(&psram), CAPACITY);
+
+    assert!(matches!(
+        block_on(NorFlash::erase(&mut psram, 20, 10)),
+        Err(DeviceError::OutOfBounds)
+    ));
+    assert!(matches!(
+        block_on(NorFlash::erase(&mut psram, 0, CAPACITY as u32 + 1)),
+        Err(DeviceError::OutOfBounds)
+    ));
+    assert_eq!(sim.violations(), []);
+}
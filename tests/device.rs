@@ -0,0 +1,60 @@
+use esp_psram::device::{Device, APS12804, APS1604M, ESP_PSRAM64, ESP_PSRAM64H, IS66WVS4M8};
+use esp_psram::psram::{Density, Freq, Identification, Variant};
+
+#[test]
+fn espressif_descriptors_carry_their_variant() {
+    assert_eq!(ESP_PSRAM64.variant, Some(Variant::EspPsram64));
+    assert_eq!(ESP_PSRAM64H.variant, Some(Variant::EspPsram64H));
+    assert_eq!(APS1604M.variant, None);
+
+    assert_eq!(Variant::EspPsram64.millivolts(), 1800);
+    assert_eq!(Variant::EspPsram64H.millivolts(), 3300);
+    assert_eq!(Variant::EspPsram64H.max_freq(), Freq::OneThreeThree);
+    for device in [ESP_PSRAM64, ESP_PSRAM64H].iter() {
+        let variant = device.variant.unwrap();
+        assert_eq!(device.max_mhz, variant.max_freq().mhz());
+    }
+}
+
+fn id(manufacturer_id: u8, density: Density) -> Identification {
+    Identification {
+        manufacturer_id,
+        kgd: 0x5D,
+        eid: 0,
+        known_good_device: true,
+        density,
+        generation: 0,
+        unique_id: 0,
+        matched: false,
+    }
+}
+
+#[test]
+fn lookup_picks_the_first_match() {
+    // The ESP-PSRAM64H is listed before anything else reporting this ID
+    assert_eq!(
+        Device::lookup(&id(0x0D, Density::Mbit64)),
+        Some(&ESP_PSRAM64H)
+    );
+    assert_eq!(Device::lookup(&id(0x0D, Density::Mbit16)), Some(&APS1604M));
+    assert_eq!(Device::lookup(&id(0x0D, Density::Mbit128)), Some(&APS12804));
+    assert_eq!(Device::lookup(&id(0x0D, Density::Mbit32)), None);
+
+    // The ISSI part matches any density
+    for &density in [Density::Mbit32, Density::Unknown(7)].iter() {
+        assert_eq!(Device::lookup(&id(0x9D, density)), Some(&IS66WVS4M8));
+    }
+    assert_eq!(Device::lookup(&id(0x42, Density::Mbit64)), None);
+
+    assert!(ESP_PSRAM64.matches(&id(0x0D, Density::Mbit64)));
+    assert!(!ESP_PSRAM64.matches(&id(0x0D, Density::Mbit16)));
+    assert!(!ESP_PSRAM64.matches(&id(0x9D, Density::Mbit64)));
+}
+
+#[test]
+fn density_sizes() {
+    assert_eq!(Density::Mbit16.bytes(), Some(APS1604M.capacity));
+    assert_eq!(Density::Mbit64.bytes(), Some(ESP_PSRAM64.capacity));
+    assert_eq!(Density::Mbit128.bytes(), Some(APS12804.capacity));
+    assert_eq!(Density::Unknown(5).bytes(), None);
+}
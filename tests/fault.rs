@@ -0,0 +1,161 @@
+use core::convert::Infallible;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::storage::{Address, MultiRead, MultiWrite};
+use esp_psram::fault::{Faults, FaultyCs, FaultySpi};
+use esp_psram::psram::{BurstLength, Freq, PSRAM};
+use esp_psram::sim::{SimCs, SimSpi, Simulator};
+
+type Psram<'a> = PSRAM<FaultySpi<'a, SimSpi>, FaultyCs<'a, SimCs>>;
+
+const FREQ: Freq = Freq::EightyFour;
+
+struct NoDelay;
+
+impl DelayUs<u32> for NoDelay {
+    type Error = Infallible;
+
+    fn try_delay_us(&mut self, _us: u32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+fn init<'a>(sim: &Simulator, faults: &'a Faults, burst: BurstLength) -> Psram<'a> {
+    PSRAM::init(faults.spi(sim.spi()), faults.cs(sim.cs()), FREQ, burst).unwrap()
+}
+
+/// Fails each operation of a call in turn, until the call gets through.
+///
+/// `attempt` gets a fresh device for every `n`, arms `faults` with `n` right
+/// before the call under test, and returns whether the call succeeded. After
+/// every attempt the bus has to be idle, on both sides of the wrappers.
+fn sweep(mut attempt: impl FnMut(&Simulator, &Faults, usize) -> bool) {
+    for n in 0.. {
+        let sim = Simulator::new(FREQ);
+        let faults = Faults::new();
+        let ok = attempt(&sim, &faults, n);
+        assert!(faults.idle(), "CS left low after failing operation {}", n);
+        assert!(
+            !sim.selected(),
+            "device left selected after failing operation {}",
+            n
+        );
+        if ok {
+            assert!(n > 0, "the call did not touch the bus");
+            return;
+        }
+    }
+}
+
+#[test]
+fn init_leaves_the_bus_idle() {
+    sweep(|sim, faults, n| {
+        faults.fail_at(n);
+        PSRAM::init(
+            faults.spi(sim.spi()),
+            faults.cs(sim.cs()),
+            FREQ,
+            BurstLength::ThirtyTwoByte,
+        )
+        .is_ok()
+    });
+}
+
+#[test]
+fn init_with_delay_leaves_the_bus_idle() {
+    sweep(|sim, faults, n| {
+        faults.fail_at(n);
+        PSRAM::init_with_delay(
+            faults.spi(sim.spi()),
+            faults.cs(sim.cs()),
+            FREQ,
+            BurstLength::ThirtyTwoByte,
+            &mut NoDelay,
+        )
+        .is_ok()
+    });
+}
+
+#[test]
+fn reset_leaves_the_bus_idle() {
+    sweep(|sim, faults, n| {
+        let mut psram = init(sim, faults, BurstLength::ThirtyTwoByte);
+        faults.fail_at(n);
+        psram.reset(&mut NoDelay).is_ok()
+    });
+}
+
+#[test]
+fn set_burst_leaves_the_bus_idle() {
+    sweep(|sim, faults, n| {
+        let mut psram = init(sim, faults, BurstLength::OneKByte);
+        faults.fail_at(n);
+        let ok = psram.set_burst(BurstLength::ThirtyTwoByte).is_ok();
+
+        // The toggle may have reached the device anyway, recover brings the
+        // device back in line with the driver
+        faults.disarm();
+        if !ok {
+            psram.recover(&mut NoDelay).unwrap();
+        }
+        assert_eq!(
+            psram.burst_length() == BurstLength::ThirtyTwoByte,
+            sim.wrap()
+        );
+        ok
+    });
+}
+
+#[test]
+fn try_read_slice_leaves_the_bus_idle() {
+    sweep(|sim, faults, n| {
+        let mut psram = init(sim, faults, BurstLength::OneKByte);
+        let mut buf = [0; 3000];
+        faults.fail_at(n);
+        psram.try_read_slice(Address(1000), &mut buf).is_ok()
+    });
+}
+
+#[test]
+fn try_write_slice_leaves_the_bus_idle() {
+    sweep(|sim, faults, n| {
+        let mut psram = init(sim, faults, BurstLength::OneKByte);
+        let mut buf = [0x5A; 3000];
+        faults.fail_at(n);
+        let ok = psram.try_write_slice(Address(1000), &mut buf).is_ok();
+
+        // A failed write can be repeated
+        faults.disarm();
+        psram
+            .try_write_slice(Address(1000), &mut [0x5A; 3000])
+            .unwrap();
+        let mut written = [0; 3000];
+        sim.peek(1000, &mut written);
+        assert!(written.iter().all(|&byte| byte == 0x5A));
+        ok
+    });
+}
+
+#[test]
+fn read_id_leaves_the_bus_idle() {
+    sweep(|sim, faults, n| {
+        let mut psram = init(sim, faults, BurstLength::OneKByte);
+        faults.fail_at(n);
+        match psram.read_id() {
+            Ok(id) => {
+                assert!(id.known_good_device);
+                true
+            }
+            Err(_) => false,
+        }
+    });
+}
+
+#[test]
+fn nothing_fails_without_fail_at() {
+    let sim = Simulator::new(FREQ);
+    let faults = Faults::new();
+    let mut psram = init(&sim, &faults, BurstLength::ThirtyTwoByte);
+    psram.read_id().unwrap();
+    assert!(faults.operations() > 0);
+    assert_eq!(sim.violations(), []);
+}
@@ -0,0 +1,179 @@
+use esp_psram::heap::{AllocError, Heap, Stats};
+
+#[test]
+fn allocates_the_smallest_block_that_fits() {
+    let mut heap: Heap<8> = Heap::new(0x1000, 3000);
+    let a = heap.allocate(1000).unwrap();
+    let b = heap.allocate(200).unwrap();
+    let c = heap.allocate(400).unwrap();
+    let d = heap.allocate(1400).unwrap();
+    assert_eq!((a.offset(), a.len()), (0x1000, 1000));
+    assert_eq!(c.offset(), 0x1000 + 1200);
+
+    // Free gaps of 1000 and 400 bytes, the second one fits best
+    heap.free(a).unwrap();
+    heap.free(c).unwrap();
+    let small = heap.allocate(300).unwrap();
+    assert_eq!(small.offset(), 0x1000 + 1200);
+
+    heap.free(small).unwrap();
+    heap.free(b).unwrap();
+    heap.free(d).unwrap();
+    assert_eq!(heap.stats().free_blocks, 1);
+}
+
+#[test]
+fn free_merges_with_both_neighbours() {
+    let mut heap: Heap<8> = Heap::new(0, 4000);
+    let a = heap.allocate(1000).unwrap();
+    let b = heap.allocate(1000).unwrap();
+    let c = heap.allocate(1000).unwrap();
+    let d = heap.allocate(1000).unwrap();
+
+    heap.free(a).unwrap();
+    heap.free(c).unwrap();
+    assert_eq!(
+        heap.stats(),
+        Stats {
+            used: 2000,
+            free: 2000,
+            largest_free: 1000,
+            used_blocks: 2,
+            free_blocks: 2,
+        }
+    );
+
+    // Both neighbours of b are free now, so all three become one block
+    heap.free(b).unwrap();
+    assert_eq!(
+        heap.stats(),
+        Stats {
+            used: 1000,
+            free: 3000,
+            largest_free: 3000,
+            used_blocks: 1,
+            free_blocks: 1,
+        }
+    );
+    assert_eq!(heap.allocate(3000).unwrap().offset(), 0);
+    heap.free(d).unwrap();
+}
+
+#[test]
+fn fragmentation() {
+    let mut heap: Heap<8> = Heap::new(0, 1000);
+    assert_eq!(heap.stats().fragmentation(), 0);
+
+    let blocks = [
+        heap.allocate(250).unwrap(),
+        heap.allocate(250).unwrap(),
+        heap.allocate(250).unwrap(),
+        heap.allocate(250).unwrap(),
+    ];
+    // Nothing free at all
+    assert_eq!(heap.stats().fragmentation(), 0);
+
+    let [first, second, third, fourth] = blocks;
+    heap.free(first).unwrap();
+    heap.free(third).unwrap();
+    // 500 bytes free, but at most 250 in one piece
+    assert_eq!(heap.stats().fragmentation(), 50);
+    assert_eq!(heap.allocate(300), Err(AllocError::OutOfMemory));
+
+    heap.free(fourth).unwrap();
+    // 750 free, 500 of them in one piece
+    assert_eq!(heap.stats().fragmentation(), 33);
+    heap.free(second).unwrap();
+    assert_eq!(heap.stats().fragmentation(), 0);
+}
+
+#[test]
+fn table_full() {
+    let mut heap: Heap<2> = Heap::new(0, 1000);
+    let first = heap.allocate(100).unwrap();
+
+    // Splitting the rest would need a third entry
+    let before = heap.stats();
+    assert_eq!(heap.allocate(100), Err(AllocError::TableFull));
+    assert_eq!(heap.stats(), before);
+
+    // Taking the whole rest doesn't split anything
+    let rest = heap.allocate(900).unwrap();
+    assert_eq!(rest.offset(), 100);
+    heap.free(first).unwrap();
+    heap.free(rest).unwrap();
+    assert_eq!(heap.stats().largest_free, 1000);
+}
+
+#[test]
+fn unknown_handles_are_given_back() {
+    let mut heap: Heap<4> = Heap::new(0, 1000);
+    let mut other: Heap<4> = Heap::new(0, 1000);
+    let alloc = heap.allocate(100).unwrap();
+
+    let alloc = other.free(alloc).unwrap_err();
+    assert_eq!(other.stats().used, 0);
+    assert_eq!((alloc.offset(), alloc.len()), (0, 100));
+    heap.free(alloc).unwrap();
+    assert_eq!(heap.stats().used, 0);
+}
+
+#[test]
+fn rejects_what_it_can_not_hand_out() {
+    let mut heap: Heap<4> = Heap::new(0, 1000);
+    assert_eq!(heap.allocate(0), Err(AllocError::ZeroSize));
+    assert_eq!(heap.allocate(1001), Err(AllocError::OutOfMemory));
+
+    let mut empty: Heap<4> = Heap::new(0, 0);
+    assert_eq!(empty.allocate(1), Err(AllocError::OutOfMemory));
+    let mut no_table: Heap<0> = Heap::new(0, 1000);
+    assert_eq!(no_table.allocate(1), Err(AllocError::OutOfMemory));
+}
+
+#[test]
+fn ends_at_the_top_of_the_address_space() {
+    let mut heap: Heap<4> = Heap::new(u32::MAX - 100, 100);
+    let first = heap.allocate(50).unwrap();
+    let second = heap.allocate(50).unwrap();
+    assert_eq!(second.offset() + second.len(), u32::MAX);
+    heap.free(first).unwrap();
+    heap.free(second).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "the heap must end within the 32 bit address space")]
+fn rejects_a_heap_past_the_address_space() {
+    let _heap: Heap<4> = Heap::new(u32::MAX - 10, 100);
+}
+
+#[cfg(feature = "sim")]
+#[test]
+fn regions_stay_within_their_bounds() {
+    use esp_psram::psram::{BurstLength, Freq, PSRAM};
+    use esp_psram::sim::Simulator;
+    use esp_psram::Error;
+
+    let sim = Simulator::new(Freq::EightyFour);
+    let mut psram =
+        PSRAM::init(sim.spi(), sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+    let mut heap: Heap<4> = Heap::new(0x100, 1000);
+    let alloc = heap.allocate(16).unwrap();
+
+    alloc.write(&mut psram, 8, &[0x5A; 8]).unwrap();
+    let mut memory = [0; 8];
+    sim.peek(0x108, &mut memory);
+    assert_eq!(memory, [0x5A; 8]);
+
+    let mut buf = [0; 8];
+    alloc.read(&mut psram, 8, &mut buf).unwrap();
+    assert_eq!(buf, [0x5A; 8]);
+    assert!(matches!(
+        alloc.read(&mut psram, 9, &mut buf),
+        Err(Error::OutOfBounds)
+    ));
+    assert!(matches!(
+        alloc.write(&mut psram, u32::MAX, &[0]),
+        Err(Error::OutOfBounds)
+    ));
+    heap.free(alloc).unwrap();
+}
@@ -0,0 +1,107 @@
+use embedded_hal::blocking::spi::Transfer;
+use esp_psram::memtest::{self, Failure};
+use esp_psram::psram::{BurstLength, Freq, PSRAM};
+use esp_psram::sim::{SimSpi, Simulator};
+
+/// Inverts the `victim` byte whenever the `aggressor` byte goes from 0x00 to 0xFF.
+///
+/// The bytes are passed on to the simulator one at a time, so the fault
+/// happens in the middle of a burst, right after the aggressor is written.
+struct CoupledSpi {
+    spi: SimSpi,
+    sim: Simulator,
+    aggressor: usize,
+    victim: usize,
+    last: u8,
+}
+
+impl Transfer<u8> for CoupledSpi {
+    type Error = <SimSpi as Transfer<u8>>::Error;
+
+    fn try_transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        for word in words.chunks_mut(1) {
+            self.spi.try_transfer(word)?;
+
+            let mut aggressor = [0];
+            self.sim.peek(self.aggressor, &mut aggressor);
+            if self.last == 0x00 && aggressor[0] == 0xFF {
+                let mut victim = [0];
+                self.sim.peek(self.victim, &mut victim);
+                self.sim.poke(self.victim, &[!victim[0]]);
+            }
+            self.last = aggressor[0];
+        }
+        Ok(words)
+    }
+}
+
+#[test]
+fn march_c_minus_passes_on_good_memory() {
+    let sim = Simulator::new(Freq::EightyFour);
+    let mut psram =
+        PSRAM::init(sim.spi(), sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+    let mut buf = [0; 1024];
+    assert_eq!(
+        memtest::march_c_minus(&mut psram, 1000..4000, &mut buf).unwrap(),
+        None
+    );
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn march_c_minus_finds_coupling_faults_within_a_burst() {
+    // Both directions, within a single burst
+    for &(aggressor, victim) in [(10, 20), (20, 10)].iter() {
+        let sim = Simulator::new(Freq::EightyFour);
+        let spi = CoupledSpi {
+            spi: sim.spi(),
+            sim: sim.clone(),
+            aggressor,
+            victim,
+            last: 0,
+        };
+        let mut psram =
+            PSRAM::init(spi, sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+        let mut buf = [0; 1024];
+
+        let failure = memtest::march_c_minus(&mut psram, 0..64, &mut buf)
+            .unwrap()
+            .expect("the coupling fault was not found");
+        assert_eq!(failure.address, victim as u32);
+    }
+}
+
+#[test]
+fn run_all_reports_the_first_failure() {
+    let sim = Simulator::new(Freq::EightyFour);
+    let spi = CoupledSpi {
+        spi: sim.spi(),
+        sim: sim.clone(),
+        aggressor: 0x100,
+        victim: 0x180,
+        last: 0,
+    };
+    let mut psram = PSRAM::init(spi, sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+    let mut buf = [0; 1024];
+    assert!(matches!(
+        memtest::run_all(&mut psram, 0x100..0x200, &mut buf).unwrap(),
+        Some(Failure { .. })
+    ));
+}
+
+#[test]
+fn run_all_leaves_an_empty_range_alone() {
+    let sim = Simulator::new(Freq::EightyFour);
+    let mut psram =
+        PSRAM::init(sim.spi(), sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+    sim.poke(100, &[0x42]);
+    let mut buf = [0; 1024];
+    assert_eq!(
+        memtest::run_all(&mut psram, 100..100, &mut buf).unwrap(),
+        None
+    );
+
+    let mut byte = [0];
+    sim.peek(100, &mut byte);
+    assert_eq!(byte, [0x42]);
+}
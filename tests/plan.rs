@@ -0,0 +1,68 @@
+use esp_psram::plan::{Planner, Transaction};
+use esp_psram::psram::{BurstLength, Freq};
+
+fn plan(planner: Planner, address: u32, len: usize) -> Vec<Transaction> {
+    planner.plan(address, len).collect()
+}
+
+#[test]
+fn splits_at_pages_above_84mhz() {
+    let planner = Planner::new(Freq::OneZeroFour, BurstLength::OneKByte, 1024, 4096).unwrap();
+    assert_eq!(planner.boundary(), Some(1024));
+    assert_eq!(
+        plan(planner, 1000, 2000),
+        [
+            Transaction {
+                address: 1000,
+                range: 0..24
+            },
+            Transaction {
+                address: 1024,
+                range: 24..1048
+            },
+            Transaction {
+                address: 2048,
+                range: 1048..2000
+            },
+        ]
+    );
+}
+
+#[test]
+fn splits_at_max_len() {
+    let planner = Planner::new(Freq::EightyFour, BurstLength::OneKByte, 1024, 100).unwrap();
+    assert_eq!(planner.boundary(), None);
+    let transactions = plan(planner, 1000, 250);
+    assert_eq!(transactions.len(), 3);
+    assert_eq!(transactions[2].address, 1200);
+    assert_eq!(transactions[2].range, 200..250);
+}
+
+#[test]
+fn splits_at_lines_in_wrap_mode() {
+    let planner = Planner::new(Freq::EightyFour, BurstLength::ThirtyTwoByte, 1024, 4096).unwrap();
+    assert_eq!(planner.boundary(), Some(32));
+    assert_eq!(plan(planner, 30, 40).len(), 3);
+}
+
+#[test]
+fn rejects_zero_page_size() {
+    assert_eq!(
+        Planner::new(Freq::OneZeroFour, BurstLength::OneKByte, 0, 64),
+        None
+    );
+}
+
+#[test]
+fn stops_at_end_of_address_space() {
+    for &freq in [Freq::EightyFour, Freq::OneZeroFour].iter() {
+        let planner = Planner::new(freq, BurstLength::OneKByte, 1024, 64).unwrap();
+        assert_eq!(
+            plan(planner, u32::MAX - 1, 4),
+            [Transaction {
+                address: u32::MAX - 1,
+                range: 0..2
+            }]
+        );
+    }
+}
@@ -0,0 +1,705 @@
+use core::cell::Cell;
+use core::convert::Infallible;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::storage::{Address, MultiRead, MultiWrite};
+use esp_psram::bus::ClockControl;
+use esp_psram::calibrate::{calibrate, Setting};
+use esp_psram::device::{Commands, Device, ESP_PSRAM64};
+use esp_psram::psram::{BurstLength, Density, Freq, Mode, ReadCommand, WriteCommand, PSRAM};
+use esp_psram::sim::{SimCs, SimSpi, Simulator, Violation, CAPACITY};
+use esp_psram::Error;
+use std::rc::Rc;
+
+type Psram = PSRAM<SimSpi, SimCs>;
+
+const FREQS: [Freq; 5] = [
+    Freq::ThreeThree,
+    Freq::EightyFour,
+    Freq::OneZeroFour,
+    Freq::OneThreeThree,
+    Freq::OneFourFour,
+];
+
+const BURSTS: [BurstLength; 3] = [
+    BurstLength::None,
+    BurstLength::ThirtyTwoByte,
+    BurstLength::OneKByte,
+];
+
+struct NoDelay;
+
+impl DelayUs<u32> for NoDelay {
+    type Error = Infallible;
+
+    fn try_delay_us(&mut self, _us: u32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+fn init(freq: Freq, burst: BurstLength) -> (Simulator, Psram) {
+    let sim = Simulator::new(freq);
+    let psram = PSRAM::init(sim.spi(), sim.cs(), freq, burst).unwrap();
+    (sim, psram)
+}
+
+/// Bytes which differ from their neighbours and repeat neither per page nor per line.
+fn pattern(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(7) ^ (i >> 8) ^ seed) as u8)
+        .collect()
+}
+
+fn peek(sim: &Simulator, address: usize, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    sim.peek(address, &mut buf);
+    buf
+}
+
+#[test]
+fn round_trip_at_every_freq_and_burst_length() {
+    for &freq in FREQS.iter() {
+        for &burst in BURSTS.iter() {
+            if burst == BurstLength::None && freq != Freq::ThreeThree {
+                continue;
+            }
+            let (sim, mut psram) = init(freq, burst);
+            assert_eq!(sim.wrap(), burst == BurstLength::ThirtyTwoByte);
+
+            // Starts and ends in the middle of a page, so every split is exercised
+            let mut data = pattern(5000, 1);
+            let expected = data.clone();
+            psram.try_write_slice(Address(1000), &mut data).unwrap();
+            assert_eq!(peek(&sim, 1000, 5000), expected);
+
+            let mut buf = vec![0; 5000];
+            psram.try_read_slice(Address(1000), &mut buf).unwrap();
+            assert_eq!(buf, expected);
+
+            let data = pattern(3000, 2);
+            psram.write_from(Address(20_000), &data).unwrap();
+            assert_eq!(peek(&sim, 20_000, 3000), data);
+
+            assert_eq!(sim.violations(), [], "{:?} {:?}", freq, burst);
+        }
+    }
+}
+
+#[test]
+fn init_with_delay_runs_the_power_up_sequence() {
+    let sim = Simulator::new(Freq::OneThreeThree);
+    let mut psram = PSRAM::init_with_delay(
+        sim.spi(),
+        sim.cs(),
+        Freq::OneThreeThree,
+        BurstLength::ThirtyTwoByte,
+        &mut NoDelay,
+    )
+    .unwrap();
+    assert!(sim.wrap());
+    assert_eq!(psram.burst_length(), BurstLength::ThirtyTwoByte);
+
+    let mut data = pattern(100, 3);
+    psram.try_write_slice(Address(0), &mut data).unwrap();
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn read_id() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    let id = psram.read_id().unwrap();
+    assert_eq!(id.manufacturer_id, 0x0D);
+    assert!(id.known_good_device);
+    assert_eq!(id.density, Density::Mbit64);
+    assert_eq!(id.unique_id, 0x1234_5678);
+    assert_eq!(psram.device().name, "ESP-PSRAM64");
+    assert_eq!(psram.capacity() as usize, CAPACITY);
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn read_id_picks_the_device() {
+    let parts = [
+        // MFID, KGD, EID[47:40] with the density code in the top three bits
+        ([0x0D, 0x5D, 0x00], Density::Mbit16, "APS1604M", 2),
+        ([0x0D, 0x5D, 0x60], Density::Mbit128, "APS12804", 16),
+        ([0x9D, 0x5D, 0x20], Density::Mbit32, "IS66WVS4M8", 4),
+        ([0x0D, 0x5D, 0x40], Density::Mbit64, "ESP-PSRAM64", 8),
+    ];
+
+    for &(prefix, density, name, megabytes) in parts.iter() {
+        let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+        sim.set_id([prefix[0], prefix[1], prefix[2], 0, 0, 0, 0, 1]);
+
+        let id = psram.read_id().unwrap();
+        assert_eq!(id.manufacturer_id, prefix[0]);
+        assert_eq!(id.density, density);
+        assert_eq!(id.unique_id, 1);
+        assert!(id.matched);
+        assert_eq!(psram.device().name, name);
+        assert_eq!(psram.capacity(), megabytes * 1024 * 1024);
+    }
+}
+
+#[test]
+fn read_id_of_an_unknown_part() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    // A 2MB part from a manufacturer missing from DEVICES
+    sim.set_id([0x42, 0x5D, 0x00, 0, 0, 0, 0, 0]);
+
+    let id = psram.read_id().unwrap();
+    assert_eq!(id.manufacturer_id, 0x42);
+    assert_eq!(id.density, Density::Mbit16);
+    assert!(!id.matched);
+    assert_eq!(psram.device().name, "ESP-PSRAM64");
+
+    // The caller picks the descriptor
+    psram
+        .set_device(Device {
+            name: "custom",
+            manufacturer_id: 0x42,
+            density: Some(Density::Mbit16),
+            capacity: 2 * 1024 * 1024,
+            ..ESP_PSRAM64
+        })
+        .unwrap();
+    assert!(psram.read_id().unwrap().matched);
+    let mut buf = [0; 4];
+    assert!(matches!(
+        psram.try_read_slice(Address(2 * 1024 * 1024 - 2), &mut buf),
+        Err(nb::Error::Other(Error::OutOfBounds))
+    ));
+
+    // The power up sequence refuses it
+    assert!(matches!(
+        PSRAM::init_with_delay(
+            sim.spi(),
+            sim.cs(),
+            Freq::EightyFour,
+            BurstLength::ThirtyTwoByte,
+            &mut NoDelay
+        ),
+        Err(Error::InvalidDevice)
+    ));
+}
+
+#[test]
+fn parts_without_fast_read_stay_at_33mhz() {
+    let slow = Device {
+        name: "slow",
+        commands: Commands {
+            fast_read: false,
+            ..ESP_PSRAM64.commands
+        },
+        ..ESP_PSRAM64
+    };
+
+    let (_sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    assert!(matches!(psram.set_device(slow), Err(Error::InvalidMode)));
+
+    let (sim, mut psram) = init(Freq::ThreeThree, BurstLength::OneKByte);
+    psram.set_device(slow).unwrap();
+    assert!(matches!(
+        psram.set_freq(Freq::EightyFour),
+        Err(Error::InvalidMode)
+    ));
+    assert_eq!(psram.freq(), Freq::ThreeThree);
+
+    let mut buf = [0; 16];
+    psram.try_read_slice(Address(0), &mut buf).unwrap();
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn quad_in_spi_mode() {
+    for &freq in [Freq::EightyFour, Freq::OneFourFour].iter() {
+        let (sim, mut psram) = init(freq, BurstLength::OneKByte);
+
+        let data = pattern(3000, 4);
+        psram.write_quad(Address(500), &data).unwrap();
+        assert_eq!(peek(&sim, 500, 3000), data);
+
+        let mut buf = vec![0; 3000];
+        psram.read_quad(Address(500), &mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        assert_eq!(sim.mode(), Mode::Spi);
+        assert_eq!(psram.mode(), Mode::Spi);
+        assert_eq!(sim.violations(), []);
+    }
+}
+
+#[test]
+fn quad_in_qpi_mode() {
+    let (sim, mut psram) = init(Freq::ThreeThree, BurstLength::OneKByte);
+    psram.enter_quad_mode().unwrap();
+    assert_eq!(sim.mode(), Mode::Qpi);
+    assert_eq!(psram.mode(), Mode::Qpi);
+
+    let data = pattern(3000, 5);
+    psram.write_quad(Address(500), &data).unwrap();
+    assert_eq!(peek(&sim, 500, 3000), data);
+    let mut buf = vec![0; 3000];
+    psram.read_quad(Address(500), &mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    // Write and Fast Read also use four lanes in QPI mode
+    let data = pattern(100, 6);
+    psram
+        .write_with(WriteCommand::Write, Address(10_000), &data)
+        .unwrap();
+    assert_eq!(peek(&sim, 10_000, 100), data);
+    let mut buf = vec![0; 100];
+    psram
+        .read_with(ReadCommand::FastRead, Address(10_000), &mut buf)
+        .unwrap();
+    assert_eq!(buf, data);
+    assert!(matches!(
+        psram.read_with(ReadCommand::Read, Address(0), &mut buf),
+        Err(Error::InvalidMode)
+    ));
+
+    // The single lane accesses are refused instead of confusing the device
+    assert!(matches!(psram.read_id(), Err(Error::InvalidMode)));
+    assert!(matches!(
+        psram.try_read_slice(Address(0), &mut buf),
+        Err(nb::Error::Other(Error::InvalidMode))
+    ));
+
+    psram.exit_quad_mode().unwrap();
+    assert_eq!(sim.mode(), Mode::Spi);
+    psram.try_read_slice(Address(10_000), &mut buf).unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn set_burst_in_qpi_mode() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    psram.enter_quad_mode().unwrap();
+    assert!(matches!(
+        psram.set_burst(BurstLength::ThirtyTwoByte),
+        Err(Error::InvalidMode)
+    ));
+
+    psram.set_burst_quad(BurstLength::ThirtyTwoByte).unwrap();
+    assert!(sim.wrap());
+    assert_eq!(psram.burst_length(), BurstLength::ThirtyTwoByte);
+
+    psram.exit_quad_mode().unwrap();
+    psram.set_burst_quad(BurstLength::OneKByte).unwrap();
+    assert!(!sim.wrap());
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn fast_read_quad_above_33mhz_in_qpi_mode() {
+    let (sim, mut psram) = init(Freq::OneThreeThree, BurstLength::OneKByte);
+    psram.enter_quad_mode().unwrap();
+
+    let mut buf = [0; 16];
+    assert!(matches!(
+        psram.read_with(ReadCommand::FastRead, Address(0), &mut buf),
+        Err(Error::InvalidMode)
+    ));
+    psram.write_quad(Address(2000), &pattern(2000, 7)).unwrap();
+    let mut buf = vec![0; 2000];
+    psram.read_quad(Address(2000), &mut buf).unwrap();
+    assert_eq!(buf, pattern(2000, 7));
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn read_and_write_wrapped() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    let mut line = [0; 32];
+    assert!(matches!(
+        psram.read_wrapped(Address(0x100), &mut line),
+        Err(Error::InvalidMode)
+    ));
+
+    psram.set_burst(BurstLength::ThirtyTwoByte).unwrap();
+    assert!(sim.wrap());
+
+    let memory = pattern(32, 8);
+    sim.poke(0x100, &memory);
+    psram.read_wrapped(Address(0x105), &mut line).unwrap();
+    for (i, byte) in line.iter().enumerate() {
+        assert_eq!(*byte, memory[(5 + i) % 32]);
+    }
+
+    let mut data = [0; 32];
+    data.copy_from_slice(&pattern(32, 9));
+    psram.write_wrapped(Address(0x21F), &data).unwrap();
+    let written = peek(&sim, 0x200, 32);
+    for (i, byte) in data.iter().enumerate() {
+        assert_eq!(written[(0x1F + i) % 32], *byte);
+    }
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn reset_and_recover() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::ThirtyTwoByte);
+    assert!(sim.wrap());
+
+    psram.reset(&mut NoDelay).unwrap();
+    assert!(!sim.wrap());
+    assert_eq!(psram.burst_length(), BurstLength::OneKByte);
+
+    psram.set_burst(BurstLength::ThirtyTwoByte).unwrap();
+    psram.recover(&mut NoDelay).unwrap();
+    assert!(sim.wrap());
+    assert_eq!(psram.burst_length(), BurstLength::ThirtyTwoByte);
+
+    psram.enter_quad_mode().unwrap();
+    assert!(matches!(psram.reset(&mut NoDelay), Err(Error::InvalidMode)));
+    psram.reset_quad(&mut NoDelay).unwrap();
+    assert_eq!(sim.mode(), Mode::Spi);
+    assert_eq!(psram.mode(), Mode::Spi);
+    assert!(!sim.wrap());
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn recover_quad() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::ThirtyTwoByte);
+    psram.enter_quad_mode().unwrap();
+    psram.recover_quad(&mut NoDelay).unwrap();
+    assert_eq!(sim.mode(), Mode::Spi);
+    assert!(sim.wrap());
+    assert_eq!(sim.violations(), []);
+
+    // From SPI mode the device ignores the QPI encoded reset
+    psram.recover_quad(&mut NoDelay).unwrap();
+    assert_eq!(sim.mode(), Mode::Spi);
+    assert!(sim.wrap());
+    assert_eq!(
+        sim.violations(),
+        [
+            Violation::WrongMode {
+                opcode: 0x66,
+                mode: Mode::Spi
+            },
+            Violation::WrongMode {
+                opcode: 0x99,
+                mode: Mode::Spi
+            },
+        ]
+    );
+}
+
+#[test]
+fn accesses_past_the_end_are_refused() {
+    let (sim, mut psram) = init(Freq::OneZeroFour, BurstLength::OneKByte);
+    let end = CAPACITY as u32;
+    let mut buf = [0; 8];
+
+    assert!(matches!(
+        psram.try_read_slice(Address(end - 4), &mut buf),
+        Err(nb::Error::Other(Error::OutOfBounds))
+    ));
+    assert!(matches!(
+        psram.try_write_slice(Address(end), &mut buf),
+        Err(nb::Error::Other(Error::OutOfBounds))
+    ));
+    assert!(matches!(
+        psram.write_from(Address(end - 1), &buf),
+        Err(Error::OutOfBounds)
+    ));
+    assert!(matches!(
+        psram.read_quad(Address(end), &mut buf),
+        Err(Error::OutOfBounds)
+    ));
+    assert!(matches!(
+        psram.read_quad(Address(u32::MAX - 2), &mut buf),
+        Err(Error::OutOfBounds)
+    ));
+    assert!(matches!(
+        psram.write_quad(Address(end - 4), &buf),
+        Err(Error::OutOfBounds)
+    ));
+
+    psram.set_burst(BurstLength::ThirtyTwoByte).unwrap();
+    let mut line = [0; 32];
+    assert!(matches!(
+        psram.read_wrapped(Address(end), &mut line),
+        Err(Error::OutOfBounds)
+    ));
+    psram.read_wrapped(Address(end - 1), &mut line).unwrap();
+
+    // Nothing reached the device
+    assert_eq!(peek(&sim, CAPACITY - 8, 8), [0; 8]);
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn shared_bus_keeps_the_drivers_apart() {
+    use esp_psram::bus::SharedBus;
+    use esp_psram::sim::SimBus;
+
+    let slow = Simulator::new(Freq::ThreeThree);
+    let fast = Simulator::new(Freq::OneThreeThree);
+    let bus = SharedBus::new(SimBus::new(&[&slow, &fast]));
+    let mut first = PSRAM::init(
+        bus.acquire(),
+        slow.cs(),
+        Freq::ThreeThree,
+        BurstLength::None,
+    )
+    .unwrap();
+    let mut second = PSRAM::init(
+        bus.acquire(),
+        fast.cs(),
+        Freq::OneThreeThree,
+        BurstLength::ThirtyTwoByte,
+    )
+    .unwrap();
+    assert!(!slow.wrap());
+    assert!(fast.wrap());
+
+    // The same address on both chips, each only sees its own data
+    let (a, b) = (pattern(3000, 11), pattern(3000, 12));
+    first.write_from(Address(100), &a).unwrap();
+    second.write_from(Address(100), &b).unwrap();
+    assert_eq!(peek(&slow, 100, 3000), a);
+    assert_eq!(peek(&fast, 100, 3000), b);
+
+    let mut buf = vec![0; 3000];
+    first.try_read_slice(Address(100), &mut buf).unwrap();
+    assert_eq!(buf, a);
+    second.try_read_slice(Address(100), &mut buf).unwrap();
+    assert_eq!(buf, b);
+
+    // Wrap mode stays with the chip it was set on
+    let mut line = [0; 32];
+    assert!(matches!(
+        first.read_wrapped(Address(0x100), &mut line),
+        Err(Error::InvalidMode)
+    ));
+    second.read_wrapped(Address(0x100), &mut line).unwrap();
+    first.set_burst(BurstLength::ThirtyTwoByte).unwrap();
+    second.set_burst(BurstLength::OneKByte).unwrap();
+    assert!(slow.wrap());
+    assert!(!fast.wrap());
+
+    assert_eq!(first.read_id().unwrap().manufacturer_id, 0x0D);
+    assert_eq!(second.read_id().unwrap().manufacturer_id, 0x0D);
+    assert_eq!(slow.violations(), []);
+    assert_eq!(fast.violations(), []);
+}
+
+/// A master with `delays` sample delay settings, of which only `good` read
+/// reliably. The others flip the lowest bit of every byte read.
+struct TunableSpi {
+    spi: SimSpi,
+    delays: u8,
+    good: core::ops::Range<u8>,
+    delay: Rc<Cell<u8>>,
+}
+
+impl Transfer<u8> for TunableSpi {
+    type Error = Infallible;
+
+    fn try_transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        self.spi.try_transfer(words)?;
+        if !self.good.contains(&self.delay.get()) {
+            words.iter_mut().for_each(|word| *word ^= 0x01);
+        }
+        Ok(words)
+    }
+}
+
+impl ClockControl for TunableSpi {
+    fn try_set_freq(&mut self, freq: Freq) -> Result<u32, Self::Error> {
+        self.spi.try_set_freq(freq)
+    }
+
+    fn sample_delays(&self) -> u8 {
+        self.delays
+    }
+
+    fn sample_delay(&self) -> u8 {
+        self.delay.get()
+    }
+
+    fn try_set_sample_delay(&mut self, delay: u8) -> Result<(), Self::Error> {
+        self.delay.set(delay);
+        Ok(())
+    }
+}
+
+#[test]
+fn calibrate_finds_the_fastest_reliable_clock() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    sim.set_reliable_freq(Some(Freq::OneZeroFour));
+    let mut buf = [0; 1024];
+
+    let setting = calibrate(&mut psram, 0x1000, &mut buf).unwrap();
+    assert_eq!(
+        setting,
+        Some(Setting {
+            freq: Freq::OneZeroFour,
+            sample_delay: 0
+        })
+    );
+    assert_eq!(psram.freq(), Freq::OneZeroFour);
+    assert_eq!(psram.clock_mhz(), 104);
+    assert_eq!(sim.freq(), Freq::OneZeroFour);
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn calibrate_picks_the_middle_of_the_sample_window() {
+    let sim = Simulator::new(Freq::EightyFour);
+    let delay = Rc::new(Cell::new(0));
+    let spi = TunableSpi {
+        spi: sim.spi(),
+        delays: 8,
+        good: 2..7,
+        delay: delay.clone(),
+    };
+    let mut psram = PSRAM::init(spi, sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+    let mut buf = [0; 256];
+
+    let setting = calibrate(&mut psram, 0, &mut buf).unwrap().unwrap();
+    assert_eq!(setting.freq, Freq::OneFourFour);
+    assert_eq!(setting.sample_delay, 4);
+    assert_eq!(delay.get(), 4);
+}
+
+#[test]
+fn calibrate_keeps_33mhz_without_a_burst_limit() {
+    let (sim, mut psram) = init(Freq::ThreeThree, BurstLength::None);
+    let mut buf = [0; 1024];
+
+    let setting = calibrate(&mut psram, 0, &mut buf).unwrap().unwrap();
+    assert_eq!(setting.freq, Freq::ThreeThree);
+    assert_eq!(psram.freq(), Freq::ThreeThree);
+    assert_eq!(sim.freq(), Freq::ThreeThree);
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn calibrate_restores_the_bus_if_nothing_passes() {
+    let sim = Simulator::new(Freq::EightyFour);
+    let delay = Rc::new(Cell::new(3));
+    let spi = TunableSpi {
+        spi: sim.spi(),
+        delays: 4,
+        good: 0..0,
+        delay: delay.clone(),
+    };
+    let mut psram = PSRAM::init(spi, sim.cs(), Freq::EightyFour, BurstLength::OneKByte).unwrap();
+    let mut buf = [0; 256];
+
+    assert_eq!(calibrate(&mut psram, 0, &mut buf).unwrap(), None);
+    assert_eq!(psram.freq(), Freq::EightyFour);
+    assert_eq!(psram.clock_mhz(), 84);
+    assert_eq!(sim.freq(), Freq::EightyFour);
+    assert_eq!(delay.get(), 3);
+}
+
+#[test]
+fn calibrate_needs_a_buffer() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    sim.set_reliable_freq(Some(Freq::ThreeThree));
+
+    assert_eq!(calibrate(&mut psram, 0, &mut []).unwrap(), None);
+    assert_eq!(psram.freq(), Freq::EightyFour);
+    assert_eq!(sim.freq(), Freq::EightyFour);
+}
+
+#[test]
+fn records_violations() {
+    let (sim, mut psram) = init(Freq::OneThreeThree, BurstLength::OneKByte);
+    let mut buf = vec![0; 4000];
+
+    // The driver splits the read into transactions which fit into tCEM
+    psram.try_read_slice(Address(100), &mut buf).unwrap();
+    assert_eq!(sim.violations(), []);
+
+    // Unless it is told the wrong limit
+    psram.set_tcem(1_000_000);
+    psram.try_read_slice(Address(100), &mut buf).unwrap();
+    let violations = sim.violations();
+    assert!(!violations.is_empty());
+    assert!(violations
+        .iter()
+        .all(|violation| matches!(violation, Violation::Tcem { .. })));
+}
+
+#[test]
+fn transactions_fit_into_tcem_at_a_slower_clock() {
+    let (sim, mut psram) = init(Freq::OneThreeThree, BurstLength::OneKByte);
+    let mut buf = vec![0; 4000];
+
+    // The master falls short of 133MHz, so every CS window takes longer
+    let mhz = sim.spi().try_set_freq(Freq::EightyFour).unwrap();
+    psram.try_read_slice(Address(100), &mut buf).unwrap();
+    assert!(!sim.violations().is_empty());
+
+    sim.clear_violations();
+    psram.set_clock_mhz(mhz);
+    assert_eq!(psram.clock_mhz(), 84);
+    psram.try_read_slice(Address(100), &mut buf).unwrap();
+    assert_eq!(sim.violations(), []);
+
+    // Never faster than freq, and reset with it
+    psram.set_clock_mhz(1000);
+    assert_eq!(psram.clock_mhz(), 133);
+    psram.set_clock_mhz(84);
+    psram.set_freq(Freq::OneZeroFour).unwrap();
+    assert_eq!(psram.clock_mhz(), 104);
+}
+
+#[test]
+fn records_protocol_errors() {
+    use embedded_hal::digital::OutputPin;
+
+    let sim = Simulator::new(Freq::OneThreeThree);
+    let (mut spi, mut cs) = (sim.spi(), sim.cs());
+    let mut transaction = |bytes: &mut [u8]| {
+        cs.try_set_low().unwrap();
+        spi.try_transfer(bytes).unwrap();
+        cs.try_set_high().unwrap();
+    };
+
+    transaction(&mut [0x99]);
+    transaction(&mut [0x03, 0x00, 0x03, 0xFE, 0, 0, 0, 0]);
+    transaction(&mut [0xF5]);
+    sim.spi().try_transfer(&mut [0]).unwrap();
+
+    assert_eq!(
+        sim.violations(),
+        [
+            Violation::ResetWithoutEnable,
+            Violation::ReadTooFast,
+            Violation::PageCrossing { address: 0x400 },
+            Violation::WrongMode {
+                opcode: 0xF5,
+                mode: Mode::Spi
+            },
+            Violation::Deselected,
+        ]
+    );
+}
+
+#[cfg(feature = "embedded-storage")]
+#[test]
+fn storage_traits() {
+    use embedded_storage::{ReadStorage, Storage};
+
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    let data = pattern(2000, 10);
+    psram.write(100, &data).unwrap();
+    let mut buf = vec![0; 2000];
+    psram.read(100, &mut buf).unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(ReadStorage::capacity(&psram), CAPACITY);
+    assert!(matches!(
+        psram.write(CAPACITY as u32, &data),
+        Err(Error::OutOfBounds)
+    ));
+    assert_eq!(sim.violations(), []);
+}
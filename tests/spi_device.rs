@@ -0,0 +1,110 @@
+use embedded_hal_1::delay::DelayNs;
+use esp_psram::psram::{BurstLength, Freq};
+use esp_psram::sim::{SimDevice, Simulator, CAPACITY};
+use esp_psram::spi_device::PsramDevice;
+use esp_psram::DeviceError;
+
+struct NoDelay;
+
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+fn pattern(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(13) ^ (i >> 8) ^ seed) as u8)
+        .collect()
+}
+
+fn init(freq: Freq, burst: BurstLength) -> (Simulator, PsramDevice<SimDevice>) {
+    let sim = Simulator::new(freq);
+    let psram = PsramDevice::init_with_delay(sim.device(), freq, burst, &mut NoDelay).unwrap();
+    (sim, psram)
+}
+
+#[test]
+fn round_trip() {
+    let setups = [
+        (Freq::ThreeThree, BurstLength::None),
+        (Freq::EightyFour, BurstLength::ThirtyTwoByte),
+        (Freq::OneThreeThree, BurstLength::OneKByte),
+    ];
+    for &(freq, burst) in setups.iter() {
+        let (sim, mut psram) = init(freq, burst);
+        assert_eq!(sim.wrap(), burst == BurstLength::ThirtyTwoByte);
+        assert!(psram.read_id().unwrap().matched);
+
+        let data = pattern(5000, 1);
+        psram.write(1000, &data).unwrap();
+        let mut memory = vec![0; 5000];
+        sim.peek(1000, &mut memory);
+        assert_eq!(memory, data);
+
+        let mut buf = vec![0; 5000];
+        psram.read(1000, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(sim.violations(), [], "{:?} {:?}", freq, burst);
+    }
+}
+
+#[test]
+fn read_and_write_wrapped() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::OneKByte);
+    let mut line = [0; 32];
+    assert!(matches!(
+        psram.read_wrapped(0x100, &mut line),
+        Err(DeviceError::InvalidMode)
+    ));
+    psram.set_burst(BurstLength::ThirtyTwoByte).unwrap();
+
+    let mut data = [0; 32];
+    data.copy_from_slice(&pattern(32, 2));
+    psram.write_wrapped(0x205, &data).unwrap();
+    let mut memory = [0; 32];
+    sim.peek(0x200, &mut memory);
+    for (i, byte) in data.iter().enumerate() {
+        assert_eq!(memory[(5 + i) % 32], *byte);
+    }
+
+    psram.read_wrapped(0x205, &mut line).unwrap();
+    assert_eq!(line, data);
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn accesses_past_the_end_are_refused() {
+    let (sim, mut psram) = init(Freq::OneZeroFour, BurstLength::ThirtyTwoByte);
+    let end = CAPACITY as u32;
+    let mut buf = [0; 8];
+    assert!(matches!(
+        psram.read(end - 4, &mut buf),
+        Err(DeviceError::OutOfBounds)
+    ));
+    assert!(matches!(
+        psram.write(u32::MAX - 2, &buf),
+        Err(DeviceError::OutOfBounds)
+    ));
+    let mut line = [0; 32];
+    assert!(matches!(
+        psram.read_wrapped(end, &mut line),
+        Err(DeviceError::OutOfBounds)
+    ));
+    assert!(matches!(
+        psram.write_wrapped(end, &line),
+        Err(DeviceError::OutOfBounds)
+    ));
+    assert_eq!(sim.violations(), []);
+}
+
+#[test]
+fn reset_and_recover() {
+    let (sim, mut psram) = init(Freq::EightyFour, BurstLength::ThirtyTwoByte);
+    psram.reset(&mut NoDelay).unwrap();
+    assert!(!sim.wrap());
+    assert_eq!(psram.burst_length(), BurstLength::OneKByte);
+
+    psram.set_burst(BurstLength::ThirtyTwoByte).unwrap();
+    psram.recover(&mut NoDelay).unwrap();
+    assert!(sim.wrap());
+    assert_eq!(sim.violations(), []);
+}